@@ -1,43 +1,476 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use reqwest::Client;
 use serde_json::json;
+use std::process::Stdio;
+use std::time::Duration;
 use std::{fs::File, io::Write, path::Path};
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
 
-use crate::AudioGeneration;
+use crate::audio_merger;
+use crate::config::{AudioFormat, RetryConfig, TtsBackendType, TtsConfig, VoiceConfig};
+use crate::markdown::FrontMatter;
+use crate::openai::OpenAIEndpoint;
+use crate::probe::{self, AudioInfo};
+use crate::retry::{self, Failure};
+use crate::{conversation::Conversation, AudioGeneration, AudioGenerator};
 
-const OPENAI_AUDIO_API: &str = "https://api.openai.com/v1/audio/speech";
+const OPENAI_SPEECH_PATH: &str = "/audio/speech";
+
+// The speech endpoint rejects inputs longer than this many characters
+const MAX_SPEECH_INPUT_CHARS: usize = 4096;
+
+// Split text into pieces the speech API accepts, preferring sentence then word boundaries
+fn split_for_speech(text: &str, max_chars: usize) -> Vec<String> {
+    let mut pieces = Vec::new();
+    let mut current = String::new();
+
+    for sentence in split_sentences(text) {
+        for part in split_long(sentence, max_chars) {
+            // Whitespace at either end is trimmed off the piece, so it doesn't count
+            let joined = current.trim_start().chars().count() + part.trim_end().chars().count();
+            if joined > max_chars {
+                if !current.trim().is_empty() {
+                    pieces.push(current.trim().to_string());
+                }
+                current.clear();
+            }
+            current.push_str(part);
+        }
+    }
+
+    if !current.trim().is_empty() {
+        pieces.push(current.trim().to_string());
+    }
+
+    pieces
+}
+
+// Sentences keep their terminating punctuation and trailing whitespace
+fn split_sentences(text: &str) -> Vec<&str> {
+    let mut sentences = Vec::new();
+    let mut start = 0;
+    let mut after_punctuation = false;
+    let mut at_boundary = false;
+
+    for (index, c) in text.char_indices() {
+        if c.is_whitespace() {
+            at_boundary |= after_punctuation || c == '\n';
+            after_punctuation = false;
+            continue;
+        }
+
+        if at_boundary {
+            sentences.push(&text[start..index]);
+            start = index;
+            at_boundary = false;
+        }
+        after_punctuation = matches!(c, '.' | '!' | '?');
+    }
+
+    if start < text.len() {
+        sentences.push(&text[start..]);
+    }
+
+    sentences
+}
+
+// A sentence that is still too long is cut at whitespace, or mid-word as a last resort
+fn split_long(sentence: &str, max_chars: usize) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut rest = sentence;
+
+    while rest.chars().count() > max_chars {
+        let limit = rest
+            .char_indices()
+            .nth(max_chars)
+            .map_or(rest.len(), |(index, _)| index);
+        let cut = rest[..limit]
+            .rfind(char::is_whitespace)
+            .filter(|&index| index > 0)
+            .unwrap_or(limit);
+        parts.push(&rest[..cut]);
+        rest = &rest[cut..];
+    }
+
+    if !rest.is_empty() {
+        parts.push(rest);
+    }
+
+    parts
+}
+
+// What a conversation was rendered to
+pub struct Speech {
+    pub turn_durations: Vec<Duration>,
+    pub info: AudioInfo,
+}
+
+pub enum TtsBackend {
+    OpenAI {
+        endpoint: OpenAIEndpoint,
+    },
+    // Runs a local TTS executable, {voice} and {output} in the arguments are substituted
+    Command {
+        program: String,
+        args: Vec<String>,
+        format: AudioFormat,
+    },
+}
+
+impl AudioGenerator {
+    pub fn new(
+        config: &TtsConfig,
+        openai: OpenAIEndpoint,
+        voices: VoiceConfig,
+        retry: RetryConfig,
+    ) -> Result<Self> {
+        if config.max_chars_per_sec.is_nan() || config.max_chars_per_sec <= 0.0 {
+            return Err(anyhow!(
+                "TTS_MAX_CHARS_PER_SEC must be a positive number, got {}",
+                config.max_chars_per_sec
+            ));
+        }
+
+        let backend = match config.backend {
+            TtsBackendType::OpenAI => {
+                if openai.is_official() && openai.api_key.is_none() {
+                    return Err(anyhow!(
+                        "OPENAI_API_KEY is required for OpenAI audio generation"
+                    ));
+                }
+                TtsBackend::OpenAI { endpoint: openai }
+            }
+            TtsBackendType::Command => {
+                let command = config.command.as_deref().ok_or_else(|| {
+                    anyhow!("TTS_COMMAND is required for the command TTS backend")
+                })?;
+                let (program, args) = split_command(command)?;
+                TtsBackend::Command {
+                    program,
+                    args,
+                    format: config.format,
+                }
+            }
+        };
+
+        Ok(Self {
+            backend,
+            voices,
+            retry,
+            max_chars_per_sec: config.max_chars_per_sec,
+        })
+    }
+
+    pub fn voices_for(&self, front_matter: &FrontMatter) -> VoiceConfig {
+        self.voices.with_overrides(&front_matter.voices)
+    }
+
+    // Which voice each speaker gets, recorded so that a voice change triggers a re-render
+    pub fn voice_assignments(&self, conversation: &Conversation, voices: &VoiceConfig) -> String {
+        let mut speakers: Vec<Option<&str>> = Vec::new();
+        for turn in &conversation.turns {
+            if !speakers.contains(&turn.speaker.as_deref()) {
+                speakers.push(turn.speaker.as_deref());
+            }
+        }
+
+        let backend = match &self.backend {
+            TtsBackend::OpenAI { endpoint } => endpoint.base_url.clone(),
+            TtsBackend::Command { program, args, .. } => format!("{} {}", program, args.join(" ")),
+        };
+        let voices: Vec<String> = speakers
+            .into_iter()
+            .map(|speaker| {
+                format!(
+                    "{}={}",
+                    speaker.unwrap_or("narrator"),
+                    voices.voice_for(speaker)
+                )
+            })
+            .collect();
+
+        format!("{} ({})", voices.join(", "), backend)
+    }
+
+    pub fn format(&self) -> AudioFormat {
+        match &self.backend {
+            TtsBackend::OpenAI { .. } => AudioFormat::Mp3,
+            TtsBackend::Command { format, .. } => *format,
+        }
+    }
+
+    // Output that doesn't decode, or is too short for its text as when a response was cut off,
+    // is requested again. Request errors were already retried by the backend.
+    async fn speak(
+        &self,
+        client: &Client,
+        text: &str,
+        voice: &str,
+        scratch_file: &Path,
+    ) -> Result<(Vec<u8>, AudioInfo)> {
+        let extension = self.format().extension();
+        let characters = text.chars().count();
+        let shortest = characters as f64 / self.max_chars_per_sec;
+
+        retry::retry(&self.retry, "Speech", || async {
+            let clip = self
+                .synthesize(client, text, voice, scratch_file)
+                .await
+                .map_err(Failure::Fatal)?;
+            let info = probe::inspect(clip.clone(), extension).map_err(Failure::retryable)?;
+            if info.duration_secs < shortest {
+                return Err(Failure::retryable(anyhow!(
+                    "{:.1}s of audio for {} characters of text, expected at least {:.1}s",
+                    info.duration_secs,
+                    characters,
+                    shortest
+                )));
+            }
+            Ok((clip, info))
+        })
+        .await
+    }
+
+    async fn synthesize(
+        &self,
+        client: &Client,
+        text: &str,
+        voice: &str,
+        scratch_file: &Path,
+    ) -> Result<Vec<u8>> {
+        match &self.backend {
+            TtsBackend::OpenAI { endpoint } => {
+                synthesize_openai(client, endpoint, &self.retry, text, voice).await
+            }
+            TtsBackend::Command { program, args, .. } => {
+                synthesize_command(program, args, text, voice, scratch_file).await
+            }
+        }
+    }
+}
+
+async fn synthesize_openai(
+    client: &Client,
+    endpoint: &OpenAIEndpoint,
+    retry: &RetryConfig,
+    text: &str,
+    voice: &str,
+) -> Result<Vec<u8>> {
+    let payload = json!({
+        "model": "tts-1",
+        "voice": voice,
+        "input": text
+    });
+
+    retry::send(retry, "OpenAI speech", || {
+        endpoint.post(client, OPENAI_SPEECH_PATH).json(&payload)
+    })
+    .await
+    .map_err(|e| e.context("Failed to generate audio"))
+}
+
+// Split like a shell would, so paths with spaces can be quoted. Placeholders are substituted per
+// argument later, a voice with spaces stays one argument.
+fn split_command(command: &str) -> Result<(String, Vec<String>)> {
+    let mut parts = shell_words::split(command)
+        .map_err(|e| anyhow!("Invalid TTS_COMMAND {}: {}", command, e))?
+        .into_iter();
+    let program = parts
+        .next()
+        .ok_or_else(|| anyhow!("TTS_COMMAND is empty"))?;
+    Ok((program, parts.collect()))
+}
+
+// Without an {output} argument the audio is read from the command's stdout
+async fn synthesize_command(
+    program: &str,
+    args: &[String],
+    text: &str,
+    voice: &str,
+    scratch_file: &Path,
+) -> Result<Vec<u8>> {
+    let writes_file = args.iter().any(|arg| arg.contains("{output}"));
+    let scratch = scratch_file.display().to_string();
+    let args = args
+        .iter()
+        .map(|arg| arg.replace("{voice}", voice).replace("{output}", &scratch));
+
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| anyhow!("Failed to start TTS command {}: {}", program, e))?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(text.as_bytes()).await?;
+    }
+
+    let output = child.wait_with_output().await?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "TTS command {} failed with {}: {}",
+            program,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    if writes_file {
+        let audio = std::fs::read(scratch_file)?;
+        std::fs::remove_file(scratch_file)?;
+        Ok(audio)
+    } else {
+        Ok(output.stdout)
+    }
+}
+
+// "4.2s, 24000 Hz mono, 48 kbps"
+fn describe(info: &AudioInfo) -> String {
+    format!(
+        "{:.1}s, {} Hz {}, {} kbps",
+        info.duration_secs,
+        info.sample_rate,
+        if info.channels == 1 { "mono" } else { "stereo" },
+        info.bitrate_kbps
+    )
+}
 
 #[async_trait::async_trait]
-impl AudioGeneration for crate::AudioGenerator {
-    async fn generate_audio(&self, conversation: &str, output_file: &Path) -> Result<()> {
+impl AudioGeneration for AudioGenerator {
+    async fn generate_audio(
+        &self,
+        conversation: &Conversation,
+        voices: &VoiceConfig,
+        output_file: &Path,
+    ) -> Result<Speech> {
         println!("Generating audio from conversation...");
         let client = Client::new();
+        let turns = &conversation.turns;
+        if turns.is_empty() {
+            return Err(anyhow::anyhow!("Conversation has no text to synthesize"));
+        }
+
+        let scratch_file =
+            output_file.with_extension(format!("part.{}", self.format().extension()));
+        let mut clips = Vec::new();
+        let mut durations = Vec::with_capacity(turns.len());
+        for (index, turn) in turns.iter().enumerate() {
+            let voice = voices.voice_for(turn.speaker.as_deref());
+            println!(
+                "  Turn {}/{}: {} ({})",
+                index + 1,
+                turns.len(),
+                turn.speaker.as_deref().unwrap_or("narrator"),
+                voice
+            );
+            let mut duration = Duration::ZERO;
+            for piece in split_for_speech(&turn.text, MAX_SPEECH_INPUT_CHARS) {
+                let (clip, info) = self.speak(&client, &piece, voice, &scratch_file).await?;
+                println!("    {}", describe(&info));
+                duration += Duration::from_secs_f64(info.duration_secs);
+                clips.push(clip);
+            }
+            durations.push(duration);
+        }
+
+        let audio_content = audio_merger::concat(self.format(), clips)?;
+        let info = probe::inspect(audio_content.clone(), self.format().extension())?;
+        let mut file = File::create(output_file)?;
+        file.write_all(&audio_content)?;
+        println!(
+            "Audio file created: {} ({})",
+            output_file.display(),
+            describe(&info)
+        );
+        Ok(Speech {
+            turn_durations: durations,
+            info,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tts_command_splits_like_a_shell() {
+        let (program, args) = split_command(
+            r#"'/opt/TTS Voices/piper' --model "{voice}" --output_file {output} --speaker\ id 3"#,
+        )
+        .unwrap();
+        assert_eq!(program, "/opt/TTS Voices/piper");
+        assert_eq!(
+            args,
+            [
+                "--model",
+                "{voice}",
+                "--output_file",
+                "{output}",
+                "--speaker id",
+                "3"
+            ]
+        );
+
+        assert!(split_command("   ").is_err());
+        assert!(split_command("piper --model 'unclosed").is_err());
+    }
 
-        let response = client
-            .post(OPENAI_AUDIO_API)
-            .header("Authorization", format!("Bearer {}", self.openai_api_key))
-            .json(&json!({
-                "model": "tts-1",
-                "voice": "alloy",
-                "input": conversation
-            }))
-            .send()
-            .await?;
-
-        let status = response.status();
-        if status.is_success() {
-            let audio_content = response.bytes().await?;
-            let mut file = File::create(output_file)?;
-            file.write_all(&audio_content)?;
-            println!("Audio file created: {}", output_file.display());
-            Ok(())
-        } else {
-            let error = response.text().await?;
-            Err(anyhow::anyhow!(
-                "Failed to generate audio. Status: {}, Error: {}",
-                status,
-                error
-            ))
+    // Every piece fits, and joined back they hold all of the text
+    fn check(text: &str, max_chars: usize) -> Vec<String> {
+        let pieces = split_for_speech(text, max_chars);
+        for piece in &pieces {
+            assert!(!piece.is_empty());
+            assert!(piece.chars().count() <= max_chars, "{:?}", piece);
         }
+        let visible = |text: &str| text.split_whitespace().collect::<String>();
+        assert_eq!(visible(&pieces.concat()), visible(text));
+        pieces
+    }
+
+    #[test]
+    fn sentences_are_packed_up_to_the_limit() {
+        let pieces = check("One two. Three four! Five six? Seven.", 20);
+        assert_eq!(pieces, ["One two. Three four!", "Five six? Seven."]);
+        assert_eq!(check("Short.", MAX_SPEECH_INPUT_CHARS), ["Short."]);
+        assert!(check("", 10).is_empty());
+    }
+
+    #[test]
+    fn long_sentence_without_whitespace_is_cut_at_the_limit() {
+        let word = "x".repeat(10_000);
+        let pieces = check(&word, MAX_SPEECH_INPUT_CHARS);
+        assert_eq!(pieces.len(), 3);
+        assert_eq!(pieces[0].len(), MAX_SPEECH_INPUT_CHARS);
+
+        check(&format!("Before. {} after", word), 100);
+        check(&format!("   {}", word), 100);
+    }
+
+    #[test]
+    fn multibyte_text_is_counted_in_characters() {
+        // Cuts near the limit must land on character boundaries
+        for max_chars in [7, 8, 9, 4096] {
+            check(&"日本語のテキスト".repeat(1000), max_chars);
+            check(&"Größe ümlaut café naïve. ".repeat(400), max_chars);
+            check(&"🎙️".repeat(5000), max_chars);
+        }
+        let pieces = check(&"é".repeat(4097), 4096);
+        assert_eq!(pieces[0].chars().count(), 4096);
+        assert_eq!(pieces[1], "é");
+    }
+
+    #[test]
+    fn newlines_separate_sentences() {
+        let text = "Jaf: first line without a stop\nsecond line\n\nthird one";
+        let pieces = check(text, 30);
+        assert_eq!(
+            pieces,
+            ["Jaf: first line without a stop", "second line\n\nthird one"]
+        );
+        check(&"a line\n".repeat(2000), 50);
     }
 }
@@ -1,40 +1,482 @@
-use anyhow::Result;
-use std::fs::File;
-use std::io::Write;
-use std::path::Path;
+use anyhow::{anyhow, Context, Result};
+use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::time::Duration;
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::DecoderOptions;
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::FormatReader;
 
-pub fn merge_audio_files(intro_path: &Path, content_path: &Path, output_path: &Path) -> Result<()> {
-    // Get absolute paths
-    let intro_abs = intro_path.canonicalize()?;
-    let content_abs = content_path.canonicalize()?;
-
-    // Create a temporary file list for ffmpeg
-    let temp_list = output_path.with_extension("txt");
-    let mut file = File::create(&temp_list)?;
-    writeln!(file, "file '{}'", intro_abs.display())?;
-    writeln!(file, "file '{}'", content_abs.display())?;
-
-    // Using ffmpeg with concat demuxer
-    let status = Command::new("ffmpeg")
-        .arg("-f")
-        .arg("concat")
-        .arg("-safe")
-        .arg("0")
-        .arg("-i")
-        .arg(&temp_list)
-        .arg("-c")
-        .arg("copy")
-        .arg(output_path)
-        .status()?;
+use crate::config::{AudioFormat, MergeBackend};
+use crate::{mp3, probe};
 
-    // Clean up the temporary file
-    std::fs::remove_file(temp_list)?;
+// One part of an episode and how it joins the audio around it
+pub struct Segment {
+    pub path: PathBuf,
+    // Silence after the segment
+    pub gap: Duration,
+    pub fade_in: Duration,
+    pub fade_out: Duration,
+    // Overlap with the end of the previous segment
+    pub crossfade: Duration,
+}
 
-    if !status.success() {
-        return Err(anyhow::anyhow!("Failed to merge audio files"));
+// Assembles the segments in order into one episode
+pub fn merge_audio_files(
+    backend: MergeBackend,
+    format: AudioFormat,
+    segments: &[Segment],
+    output_path: &Path,
+) -> Result<()> {
+    if segments.is_empty() {
+        return Err(anyhow!("No segments to merge"));
+    }
+
+    match backend {
+        MergeBackend::Native => {
+            let episode = match format {
+                AudioFormat::Mp3 => assemble_mp3(segments)?,
+                AudioFormat::Wav => assemble_wav(segments)?,
+            };
+            std::fs::write(output_path, episode)?;
+        }
+        MergeBackend::Ffmpeg => merge_with_ffmpeg(segments, output_path)?,
     }
 
     println!("Created merged audio: {}", output_path.display());
     Ok(())
 }
+
+// mp3 frames can't be mixed without re-encoding. Fades step the frames' global gain instead,
+// gaps are silent frames, and crossfades are left to ffmpeg (MergeConfig::check rejects them).
+fn assemble_mp3(segments: &[Segment]) -> Result<Vec<u8>> {
+    let mut episode: Vec<Vec<u8>> = Vec::new();
+    let mut first_format = None;
+
+    for segment in segments {
+        let mut reader = probe::open(&segment.path)?;
+        let format = stream_format(reader.as_ref())?;
+        check_same_format(&mut first_format, format, "mp3")?;
+
+        let mut frames = Vec::new();
+        while let Some(packet) = next_packet(reader.as_mut())? {
+            frames.push(packet.data.to_vec());
+        }
+        let Some(template) = frames.first().cloned() else {
+            continue;
+        };
+
+        let frame_secs = mp3::samples_per_frame(&template) as f64 / format.0 as f64;
+        let count = |duration: Duration| (duration.as_secs_f64() / frame_secs).round() as usize;
+
+        fade_frames(&mut frames, count(segment.fade_in), true);
+        fade_frames(&mut frames, count(segment.fade_out), false);
+
+        episode.extend(frames);
+        episode.extend(std::iter::repeat_n(
+            mp3::silent_frame(&template),
+            count(segment.gap),
+        ));
+    }
+
+    Ok(episode.concat())
+}
+
+// Ramps the level over the first (fading in) or last count frames in global gain steps
+fn fade_frames(frames: &mut [Vec<u8>], count: usize, fade_in: bool) {
+    let count = count.min(frames.len());
+    let last = frames.len().saturating_sub(1);
+    for step in 0..count {
+        let level = (step as f64 + 0.5) / count as f64;
+        let steps = (20.0 * level.log10() / mp3::GAIN_STEP_DB).round() as i32;
+        let index = if fade_in { step } else { last - step };
+        mp3::adjust_gain(&mut frames[index], steps);
+    }
+}
+
+fn assemble_wav(segments: &[Segment]) -> Result<Vec<u8>> {
+    let mut episode = Pcm::default();
+    let mut first_format = None;
+
+    for segment in segments {
+        let mut pcm = decode(probe::open(&segment.path)?)?;
+        check_same_format(&mut first_format, (pcm.sample_rate, pcm.channels), "wav")?;
+        episode.sample_rate = pcm.sample_rate;
+        episode.channels = pcm.channels;
+
+        pcm.fade(segment.fade_in, segment.fade_out);
+        episode.append(pcm, segment.crossfade);
+        episode.append_silence(segment.gap);
+    }
+
+    Ok(episode.to_wav())
+}
+
+// Joins clips in memory: mp3 frame by frame, wav by decoding and writing the samples out again
+pub fn concat(format: AudioFormat, clips: Vec<Vec<u8>>) -> Result<Vec<u8>> {
+    if clips.is_empty() {
+        return Err(anyhow!("No audio clips to join"));
+    }
+    match format {
+        AudioFormat::Mp3 => join_mp3(clips),
+        AudioFormat::Wav => join_wav(clips),
+    }
+}
+
+// Copies the mp3 frames of every clip back to back. The demuxer leaves out ID3 tags and
+// Xing/Info headers, which would otherwise end up in the middle of the joined stream.
+fn join_mp3(clips: Vec<Vec<u8>>) -> Result<Vec<u8>> {
+    let mut joined = Vec::new();
+    let mut first_format = None;
+
+    for clip in clips {
+        let mut reader = probe::open_bytes(clip, "mp3")?;
+        let format = stream_format(reader.as_ref())?;
+        check_same_format(&mut first_format, format, "mp3")?;
+
+        while let Some(packet) = next_packet(reader.as_mut())? {
+            joined.extend_from_slice(&packet.data);
+        }
+    }
+
+    Ok(joined)
+}
+
+fn join_wav(clips: Vec<Vec<u8>>) -> Result<Vec<u8>> {
+    let mut joined = Pcm::default();
+    let mut first_format = None;
+
+    for clip in clips {
+        let pcm = decode(probe::open_bytes(clip, "wav")?)?;
+        check_same_format(&mut first_format, (pcm.sample_rate, pcm.channels), "wav")?;
+        joined.sample_rate = pcm.sample_rate;
+        joined.channels = pcm.channels;
+        joined.append(pcm, Duration::ZERO);
+    }
+
+    Ok(joined.to_wav())
+}
+
+// Frame-level and sample-level joins need every clip at the same sample rate and channel count
+fn check_same_format(
+    first: &mut Option<(u32, usize)>,
+    format: (u32, usize),
+    extension: &str,
+) -> Result<()> {
+    match first {
+        None => *first = Some(format),
+        Some(expected) if *expected != format => {
+            return Err(anyhow!(
+                "Cannot join {} clips with different formats ({} Hz, {} channels and {} Hz, {} channels)",
+                extension,
+                expected.0,
+                expected.1,
+                format.0,
+                format.1
+            ))
+        }
+        Some(_) => {}
+    }
+    Ok(())
+}
+
+pub fn stream_format(reader: &dyn FormatReader) -> Result<(u32, usize)> {
+    let params = &reader
+        .default_track()
+        .ok_or_else(|| anyhow!("Clip has no audio track"))?
+        .codec_params;
+    let sample_rate = params
+        .sample_rate
+        .ok_or_else(|| anyhow!("Clip has no sample rate"))?;
+    let channels = params.channels.map_or(1, |channels| channels.count());
+    Ok((sample_rate, channels))
+}
+
+// None at the end of the stream
+pub fn next_packet(
+    reader: &mut dyn FormatReader,
+) -> Result<Option<symphonia::core::formats::Packet>> {
+    match reader.next_packet() {
+        Ok(packet) => Ok(Some(packet)),
+        Err(SymphoniaError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+            Ok(None)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+// Decoded audio as interleaved samples in -1.0..=1.0
+#[derive(Default)]
+pub struct Pcm {
+    pub sample_rate: u32,
+    pub channels: usize,
+    pub samples: Vec<f32>,
+}
+
+impl Pcm {
+    fn frame_count(&self) -> usize {
+        self.samples.len() / self.channels.max(1)
+    }
+
+    fn frames_in(&self, duration: Duration) -> usize {
+        (duration.as_secs_f64() * self.sample_rate as f64).round() as usize
+    }
+
+    // Linear ramps at the start and end
+    fn fade(&mut self, fade_in: Duration, fade_out: Duration) {
+        let total = self.frame_count();
+        let fade_in = self.frames_in(fade_in).min(total);
+        let fade_out = self.frames_in(fade_out).min(total);
+
+        for (frame, samples) in self.samples.chunks_mut(self.channels).enumerate() {
+            let mut gain = 1.0;
+            if frame < fade_in {
+                gain *= frame as f32 / fade_in as f32;
+            }
+            let from_end = total - frame - 1;
+            if from_end < fade_out {
+                gain *= from_end as f32 / fade_out as f32;
+            }
+            for sample in samples {
+                *sample *= gain;
+            }
+        }
+    }
+
+    // Mixes the start of next over the last crossfade of this audio, keeping the overall
+    // level steady with an equal-power curve, and appends the rest
+    fn append(&mut self, next: Pcm, crossfade: Duration) {
+        let overlap = self
+            .frames_in(crossfade)
+            .min(self.frame_count())
+            .min(next.frame_count());
+        let start = self.samples.len() - overlap * self.channels;
+
+        for (index, sample) in next.samples[..overlap * self.channels].iter().enumerate() {
+            let t = (index / self.channels) as f32 / overlap as f32 * std::f32::consts::FRAC_PI_2;
+            let mixed = &mut self.samples[start + index];
+            *mixed = *mixed * t.cos() + sample * t.sin();
+        }
+        self.samples
+            .extend_from_slice(&next.samples[overlap * self.channels..]);
+    }
+
+    fn append_silence(&mut self, duration: Duration) {
+        let len = self.samples.len() + self.frames_in(duration) * self.channels;
+        self.samples.resize(len, 0.0);
+    }
+
+    // 16-bit PCM, what local TTS engines write and every player reads
+    pub fn to_wav(&self) -> Vec<u8> {
+        let data_len = self.samples.len() * 2;
+        let block_align = self.channels * 2;
+
+        let mut wav = Vec::with_capacity(44 + data_len);
+        wav.extend_from_slice(b"RIFF");
+        wav.extend_from_slice(&((36 + data_len) as u32).to_le_bytes());
+        wav.extend_from_slice(b"WAVEfmt ");
+        wav.extend_from_slice(&16u32.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&(self.channels as u16).to_le_bytes());
+        wav.extend_from_slice(&self.sample_rate.to_le_bytes());
+        wav.extend_from_slice(&(self.sample_rate * block_align as u32).to_le_bytes());
+        wav.extend_from_slice(&(block_align as u16).to_le_bytes());
+        wav.extend_from_slice(&16u16.to_le_bytes());
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&(data_len as u32).to_le_bytes());
+        for sample in &self.samples {
+            let value = (sample * 32768.0).round().clamp(-32768.0, 32767.0) as i16;
+            wav.extend_from_slice(&value.to_le_bytes());
+        }
+        wav
+    }
+}
+
+pub fn decode(reader: Box<dyn FormatReader>) -> Result<Pcm> {
+    let (sample_rate, channels) = stream_format(reader.as_ref())?;
+    let mut samples = Vec::new();
+    decode_with(reader, |block| samples.extend_from_slice(block))?;
+    Ok(Pcm {
+        sample_rate,
+        channels,
+        samples,
+    })
+}
+
+// Decodes packet by packet, handing over the interleaved samples without keeping them
+pub fn decode_with(mut reader: Box<dyn FormatReader>, mut f: impl FnMut(&[f32])) -> Result<()> {
+    let track = reader
+        .default_track()
+        .ok_or_else(|| anyhow!("Clip has no audio track"))?;
+    let track_id = track.id;
+    let mut decoder = symphonia::default::get_codecs()
+        .make(&track.codec_params, &DecoderOptions::default())
+        .context("Unsupported audio codec")?;
+
+    while let Some(packet) = next_packet(reader.as_mut())? {
+        if packet.track_id() != track_id {
+            continue;
+        }
+        let decoded = decoder.decode(&packet)?;
+        let mut buffer = SampleBuffer::<f32>::new(decoded.capacity() as u64, *decoded.spec());
+        buffer.copy_interleaved_ref(decoded);
+        f(buffer.samples());
+    }
+    Ok(())
+}
+
+// ffmpeg re-encodes, so it can do real crossfades and join parts with different formats
+fn merge_with_ffmpeg(segments: &[Segment], output_path: &Path) -> Result<()> {
+    let mut command = Command::new("ffmpeg");
+    command.arg("-y");
+
+    let mut filters = Vec::new();
+    for (index, segment) in segments.iter().enumerate() {
+        command.arg("-i").arg(&segment.path);
+
+        let mut chain = Vec::new();
+        if !segment.fade_in.is_zero() {
+            chain.push(format!("afade=t=in:d={:.3}", segment.fade_in.as_secs_f64()));
+        }
+        if !segment.fade_out.is_zero() {
+            // afade needs to know where the fade starts
+            let duration = probe::duration(&segment.path)?;
+            chain.push(format!(
+                "afade=t=out:st={:.3}:d={:.3}",
+                duration.saturating_sub(segment.fade_out).as_secs_f64(),
+                segment.fade_out.as_secs_f64()
+            ));
+        }
+        if !segment.gap.is_zero() {
+            chain.push(format!("apad=pad_dur={:.3}", segment.gap.as_secs_f64()));
+        }
+        if chain.is_empty() {
+            chain.push(String::from("anull"));
+        }
+        filters.push(format!("[{}:a]{}[s{}]", index, chain.join(","), index));
+    }
+
+    let mut previous = String::from("s0");
+    for (index, segment) in segments.iter().enumerate().skip(1) {
+        let joined = format!("j{}", index);
+        if segment.crossfade.is_zero() {
+            filters.push(format!(
+                "[{}][s{}]concat=n=2:v=0:a=1[{}]",
+                previous, index, joined
+            ));
+        } else {
+            filters.push(format!(
+                "[{}][s{}]acrossfade=d={:.3}[{}]",
+                previous,
+                index,
+                segment.crossfade.as_secs_f64(),
+                joined
+            ));
+        }
+        previous = joined;
+    }
+
+    let status = command
+        .arg("-filter_complex")
+        .arg(filters.join(";"))
+        .arg("-map")
+        .arg(format!("[{}]", previous))
+        .arg(output_path)
+        .status()
+        .map_err(|e| anyhow!("Failed to run ffmpeg (MERGE_BACKEND=ffmpeg): {}", e))?;
+
+    if !status.success() {
+        return Err(anyhow::anyhow!("Failed to merge audio files"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Constant samples, one value per channel, at 10 frames per millisecond
+    fn pcm(frames: usize, value: f32, channels: usize) -> Pcm {
+        Pcm {
+            sample_rate: 10_000,
+            channels,
+            samples: vec![value; frames * channels],
+        }
+    }
+
+    #[test]
+    fn fades_ramp_from_and_to_silence() {
+        let mut audio = pcm(100, 1.0, 2);
+        audio.fade(Duration::from_millis(2), Duration::from_millis(4));
+
+        let left: Vec<f32> = audio.samples.iter().step_by(2).copied().collect();
+        assert_eq!(audio.samples[..2], [0.0, 0.0]);
+        assert_eq!(left[10], 0.5);
+        assert_eq!(left[20..60], [1.0; 40]);
+        assert_eq!(left[79], 0.5);
+        assert_eq!(left[99], 0.0);
+        assert!(left.windows(2).take(20).all(|pair| pair[0] < pair[1]));
+        assert!(audio.samples.chunks(2).all(|frame| frame[0] == frame[1]));
+    }
+
+    #[test]
+    fn fades_longer_than_the_audio_are_cut_to_it() {
+        let mut audio = pcm(10, 1.0, 1);
+        audio.fade(Duration::from_secs(1), Duration::ZERO);
+        assert_eq!(audio.samples[0], 0.0);
+        assert_eq!(audio.samples[5], 0.5);
+    }
+
+    #[test]
+    fn append_without_crossfade_concatenates() {
+        let mut audio = pcm(30, 0.25, 2);
+        audio.append(pcm(20, 0.5, 2), Duration::ZERO);
+        audio.append_silence(Duration::from_millis(1));
+
+        assert_eq!(audio.frame_count(), 60);
+        assert_eq!(audio.samples[59], 0.25);
+        assert_eq!(audio.samples[60], 0.5);
+        assert_eq!(audio.samples[100..], [0.0; 20]);
+    }
+
+    #[test]
+    fn crossfade_mixes_the_overlap_at_equal_power() {
+        let mut audio = pcm(50, 1.0, 1);
+        audio.append(pcm(50, 1.0, 1), Duration::from_millis(2));
+
+        assert_eq!(audio.frame_count(), 80);
+        assert_eq!(audio.samples[30], 1.0);
+        // cos + sin of the same angle, at most sqrt(2) halfway through
+        let middle = audio.samples[40];
+        assert!(
+            (middle - std::f32::consts::SQRT_2).abs() < 1e-5,
+            "{}",
+            middle
+        );
+        assert!(audio.samples[30..50].iter().all(|sample| *sample >= 1.0));
+        assert_eq!(audio.samples[50..], [1.0; 30]);
+    }
+
+    #[test]
+    fn crossfade_is_limited_by_the_shorter_part() {
+        let mut audio = pcm(10, 1.0, 1);
+        audio.append(pcm(100, 0.5, 1), Duration::from_secs(1));
+        assert_eq!(audio.frame_count(), 100);
+
+        let mut audio = pcm(100, 1.0, 1);
+        audio.append(pcm(10, 0.5, 1), Duration::from_secs(1));
+        assert_eq!(audio.frame_count(), 100);
+    }
+
+    #[test]
+    fn wav_round_trip() {
+        let mut audio = pcm(100, 0.5, 2);
+        audio.samples[1] = -1.0;
+        let decoded = decode(probe::open_bytes(audio.to_wav(), "wav").unwrap()).unwrap();
+
+        assert_eq!((decoded.sample_rate, decoded.channels), (10_000, 2));
+        assert_eq!(decoded.samples.len(), audio.samples.len());
+        assert_eq!(decoded.samples[0], 0.5);
+        assert_eq!(decoded.samples[1], -1.0);
+    }
+}
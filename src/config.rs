@@ -1,26 +1,353 @@
 use anyhow::{anyhow, Result};
 use dotenv::dotenv;
 use serde::Deserialize;
+use std::collections::HashMap;
 use std::fmt;
 use std::path::PathBuf;
+use std::str::FromStr;
 
 #[derive(Debug, Deserialize)]
 pub struct Config {
     pub input: InputConfig,
     pub model: ModelConfig,
     pub output: OutputConfig,
+    #[serde(default)]
+    pub voices: VoiceConfig,
+    #[serde(default)]
+    pub tts: TtsConfig,
+    #[serde(default)]
+    pub concurrency: ConcurrencyConfig,
+    #[serde(default)]
+    pub retry: RetryConfig,
+    #[serde(default)]
+    pub prompt: PromptConfig,
+    #[serde(default)]
+    pub feed: FeedConfig,
+    #[serde(default)]
+    pub merge: MergeConfig,
+    #[serde(default)]
+    pub loudness: LoudnessConfig,
+    #[serde(default)]
+    pub tags: TagsConfig,
+}
+
+// Podcast RSS feed written next to the merged episodes
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct FeedConfig {
+    pub title: String,
+    pub description: String,
+    // Public URL the output directory is served from, episode URLs are resolved against it
+    pub base_url: Option<String>,
+    pub author: Option<String>,
+    pub language: String,
+    // Cover art URL, podcast directories want a square image of at least 1400px
+    pub image: Option<String>,
+    pub category: Option<String>,
+    pub explicit: bool,
+}
+
+impl Default for FeedConfig {
+    fn default() -> Self {
+        Self {
+            title: String::from("NIPs Conversations"),
+            description: String::from(
+                "Audio conversations generated from technical documentation.",
+            ),
+            base_url: None,
+            author: None,
+            language: String::from("en"),
+            image: None,
+            category: Some(String::from("Technology")),
+            explicit: false,
+        }
+    }
+}
+
+impl FeedConfig {
+    fn from_env() -> Result<Self> {
+        let defaults = Self::default();
+        Ok(Self {
+            title: env_or("FEED_TITLE", defaults.title)?,
+            description: env_or("FEED_DESCRIPTION", defaults.description)?,
+            base_url: env_opt("FEED_BASE_URL")?,
+            author: env_opt("FEED_AUTHOR")?,
+            language: env_or("FEED_LANGUAGE", defaults.language)?,
+            image: env_opt("FEED_IMAGE")?,
+            category: env_opt("FEED_CATEGORY")?.or(defaults.category),
+            explicit: env_or("FEED_EXPLICIT", defaults.explicit)?,
+        })
+    }
+}
+
+// Which prompt preset conversations are generated with and the values its templates see
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct PromptConfig {
+    pub preset: String,
+    // Presets are looked up here as <name>.toml before the built-in ones
+    pub dir: Option<PathBuf>,
+    pub speakers: Vec<String>,
+    // Rough number of words per generated part
+    pub target_length: usize,
+}
+
+impl Default for PromptConfig {
+    fn default() -> Self {
+        Self {
+            preset: String::from("conversation"),
+            dir: None,
+            speakers: vec![String::from("Jaf"), String::from("Paul")],
+            target_length: 600,
+        }
+    }
+}
+
+impl PromptConfig {
+    fn from_env() -> Result<Self> {
+        let defaults = Self::default();
+        Ok(Self {
+            preset: env_or("PROMPT_PRESET", defaults.preset)?,
+            dir: env_opt("PROMPT_DIR")?,
+            speakers: env_list("PROMPT_SPEAKERS").unwrap_or(defaults.speakers),
+            target_length: env_or("PROMPT_TARGET_LENGTH", defaults.target_length)?,
+        })
+    }
+}
+
+// Retries for HTTP calls to the LLM and TTS providers
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct RetryConfig {
+    pub max_retries: u32,
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+    // Per attempt, long enough for a slow local model to finish a chunk
+    pub timeout_secs: u64,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial_backoff_ms: 1000,
+            max_backoff_ms: 60_000,
+            timeout_secs: 300,
+        }
+    }
+}
+
+impl RetryConfig {
+    fn from_env() -> Result<Self> {
+        let defaults = Self::default();
+        Ok(Self {
+            max_retries: env_or("RETRY_MAX", defaults.max_retries)?,
+            initial_backoff_ms: env_or("RETRY_INITIAL_BACKOFF_MS", defaults.initial_backoff_ms)?,
+            max_backoff_ms: env_or("RETRY_MAX_BACKOFF_MS", defaults.max_backoff_ms)?,
+            timeout_secs: env_or("HTTP_TIMEOUT_SECS", defaults.timeout_secs)?,
+        })
+    }
+}
+
+// How many documents each stage works on at once
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct ConcurrencyConfig {
+    // Conversation generation, per provider: a local Ollama usually handles one request at a time
+    pub ollama: usize,
+    pub openai: usize,
+    pub anthropic: usize,
+    pub gemini: usize,
+    pub compatible: usize,
+    pub tts: usize,
+    pub merge: usize,
+}
+
+impl Default for ConcurrencyConfig {
+    fn default() -> Self {
+        Self {
+            ollama: 1,
+            openai: 4,
+            anthropic: 4,
+            gemini: 4,
+            compatible: 2,
+            tts: 4,
+            merge: 2,
+        }
+    }
+}
+
+impl ConcurrencyConfig {
+    pub fn llm(&self, model_type: &ModelType) -> usize {
+        match model_type {
+            ModelType::Ollama(_) => self.ollama,
+            ModelType::OpenAI(_) => self.openai,
+            ModelType::Anthropic(_) => self.anthropic,
+            ModelType::Gemini(_) => self.gemini,
+            ModelType::Compatible(_) => self.compatible,
+        }
+    }
+
+    fn from_env() -> Result<Self> {
+        let defaults = Self::default();
+        Ok(Self {
+            ollama: env_or("CONCURRENCY_OLLAMA", defaults.ollama)?,
+            openai: env_or("CONCURRENCY_OPENAI", defaults.openai)?,
+            anthropic: env_or("CONCURRENCY_ANTHROPIC", defaults.anthropic)?,
+            gemini: env_or("CONCURRENCY_GEMINI", defaults.gemini)?,
+            compatible: env_or("CONCURRENCY_COMPATIBLE", defaults.compatible)?,
+            tts: env_or("CONCURRENCY_TTS", defaults.tts)?,
+            merge: env_or("CONCURRENCY_MERGE", defaults.merge)?,
+        })
+    }
 }
 
 #[derive(Debug, Deserialize)]
 pub struct InputConfig {
     pub docs_path: PathBuf,
+    // Globs matched against paths relative to docs_path
+    #[serde(default = "default_include")]
+    pub include: Vec<String>,
+    #[serde(default = "default_exclude")]
+    pub exclude: Vec<String>,
+    // 1 only looks at files directly in docs_path
+    #[serde(default)]
+    pub max_depth: Option<usize>,
+    // Dot files and everything under dot directories are skipped unless this is set
+    #[serde(default)]
+    pub include_hidden: bool,
+}
+
+fn default_include() -> Vec<String> {
+    vec![String::from("**/*.md")]
+}
+
+fn default_exclude() -> Vec<String> {
+    vec![
+        String::from("**/README.md"),
+        String::from("**/CHANGELOG.md"),
+        String::from("**/node_modules/**"),
+    ]
 }
 
 #[derive(Debug, Deserialize)]
 pub struct ModelConfig {
     pub model_type: ModelType,
     pub openai_api_key: Option<String>,
+    // Any OpenAI-compatible server (vLLM, LM Studio, LiteLLM...), defaults to api.openai.com
+    pub openai_base_url: Option<String>,
+    // Extra headers sent with every chat and speech request
+    #[serde(default)]
+    pub openai_headers: HashMap<String, String>,
     pub ollama_base_url: Option<String>,
+    // Markdown longer than this is split along headings and converted chunk by chunk
+    #[serde(default = "default_chunk_size")]
+    pub chunk_size: usize,
+    #[serde(default)]
+    pub ollama: OllamaConfig,
+    #[serde(default)]
+    pub anthropic: AnthropicConfig,
+    #[serde(default)]
+    pub gemini: GeminiConfig,
+    #[serde(default)]
+    pub compatible: CompatibleConfig,
+}
+
+// Anthropic Messages API
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct AnthropicConfig {
+    pub api_key: Option<String>,
+    pub base_url: Option<String>,
+    // The Messages API requires an explicit cap on the reply length
+    pub max_tokens: u32,
+}
+
+impl Default for AnthropicConfig {
+    fn default() -> Self {
+        Self {
+            api_key: None,
+            base_url: None,
+            max_tokens: 4096,
+        }
+    }
+}
+
+impl AnthropicConfig {
+    fn from_env() -> Result<Self> {
+        let defaults = Self::default();
+        Ok(Self {
+            api_key: std::env::var("ANTHROPIC_API_KEY")
+                .ok()
+                .filter(|key| !key.is_empty()),
+            base_url: std::env::var("ANTHROPIC_BASE_URL").ok(),
+            max_tokens: env_or("ANTHROPIC_MAX_TOKENS", defaults.max_tokens)?,
+        })
+    }
+}
+
+// Google Gemini generateContent API
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct GeminiConfig {
+    pub api_key: Option<String>,
+    pub base_url: Option<String>,
+}
+
+impl GeminiConfig {
+    fn from_env() -> Self {
+        Self {
+            api_key: std::env::var("GEMINI_API_KEY")
+                .ok()
+                .filter(|key| !key.is_empty()),
+            base_url: std::env::var("GEMINI_BASE_URL").ok(),
+        }
+    }
+}
+
+// A second OpenAI-compatible chat server, independent of the OpenAI settings used for speech
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct CompatibleConfig {
+    pub api_key: Option<String>,
+    pub base_url: Option<String>,
+    pub headers: HashMap<String, String>,
+}
+
+impl CompatibleConfig {
+    fn from_env() -> Result<Self> {
+        Ok(Self {
+            api_key: std::env::var("COMPATIBLE_API_KEY")
+                .ok()
+                .filter(|key| !key.is_empty()),
+            base_url: std::env::var("COMPATIBLE_BASE_URL").ok(),
+            headers: env_pairs("COMPATIBLE_EXTRA_HEADERS")?.unwrap_or_default(),
+        })
+    }
+}
+
+// Generation options passed to Ollama, unset values keep the model's defaults
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct OllamaConfig {
+    pub temperature: Option<f32>,
+    pub num_ctx: Option<u32>,
+    pub seed: Option<i32>,
+    pub timeout_secs: Option<u64>,
+}
+
+impl OllamaConfig {
+    fn from_env() -> Result<Self> {
+        Ok(Self {
+            temperature: env_opt("OLLAMA_TEMPERATURE")?,
+            num_ctx: env_opt("OLLAMA_NUM_CTX")?,
+            seed: env_opt("OLLAMA_SEED")?,
+            timeout_secs: env_opt("OLLAMA_TIMEOUT_SECS")?,
+        })
+    }
+}
+
+fn default_chunk_size() -> usize {
+    4000
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -28,6 +355,9 @@ pub struct ModelConfig {
 pub enum ModelType {
     Ollama(String),
     OpenAI(String),
+    Anthropic(String),
+    Gemini(String),
+    Compatible(String),
 }
 
 impl fmt::Display for ModelType {
@@ -35,6 +365,9 @@ impl fmt::Display for ModelType {
         match self {
             ModelType::Ollama(model) => write!(f, "{}", model),
             ModelType::OpenAI(model) => write!(f, "{}", model),
+            ModelType::Anthropic(model) => write!(f, "{}", model),
+            ModelType::Gemini(model) => write!(f, "{}", model),
+            ModelType::Compatible(model) => write!(f, "{}", model),
         }
     }
 }
@@ -44,16 +377,448 @@ pub struct OutputConfig {
     pub audio_path: PathBuf,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct TtsConfig {
+    #[serde(default)]
+    pub backend: TtsBackendType,
+    // Local TTS executable and arguments for the command backend, e.g.
+    // "piper --model {voice} --output_file {output}", split with shell quoting. The text is
+    // written to stdin.
+    pub command: Option<String>,
+    // Format the command backend produces, OpenAI speech is always mp3
+    #[serde(default)]
+    pub format: AudioFormat,
+    // Clips shorter than their text at this speaking rate are taken as truncated and redone.
+    // Conversational speech runs around 15 characters a second.
+    #[serde(default = "default_max_chars_per_sec")]
+    pub max_chars_per_sec: f64,
+}
+
+impl Default for TtsConfig {
+    fn default() -> Self {
+        Self {
+            backend: TtsBackendType::default(),
+            command: None,
+            format: AudioFormat::default(),
+            max_chars_per_sec: default_max_chars_per_sec(),
+        }
+    }
+}
+
+fn default_max_chars_per_sec() -> f64 {
+    40.0
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TtsBackendType {
+    #[default]
+    OpenAI,
+    Command,
+}
+
+// How episodes are assembled from the intro, the conversation and optional jingles
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct MergeConfig {
+    pub backend: MergeBackend,
+    // Episode layout in playing order
+    pub segments: Vec<SegmentConfig>,
+}
+
+impl Default for MergeConfig {
+    fn default() -> Self {
+        Self {
+            backend: MergeBackend::default(),
+            segments: vec![
+                SegmentConfig::new(SegmentSource::Intro),
+                SegmentConfig::new(SegmentSource::Body),
+            ],
+        }
+    }
+}
+
+impl MergeConfig {
+    fn from_env() -> Result<Self> {
+        let defaults = Self::default();
+        let segments = match std::env::var("MERGE_SEGMENTS") {
+            Ok(value) => value
+                .split(',')
+                .filter(|segment| !segment.trim().is_empty())
+                .map(str::parse)
+                .collect::<Result<_>>()?,
+            Err(_) => defaults.segments,
+        };
+        Ok(Self {
+            backend: env_or("MERGE_BACKEND", defaults.backend)?,
+            segments,
+        })
+    }
+
+    // A crossfade overlaps the audio before it, after a gap that would only be the silence
+    pub fn check(&self, format: AudioFormat) -> Result<()> {
+        for pair in self.segments.windows(2) {
+            if pair[0].gap_ms > 0 && pair[1].crossfade_ms > 0 {
+                return Err(anyhow!(
+                    "Episode segment {} has a gap but {} crossfades into it, use one or the other",
+                    pair[0].source,
+                    pair[1].source
+                ));
+            }
+        }
+
+        // mp3 frames are copied, not mixed, so nothing can overlap without re-encoding
+        let crossfade = self
+            .segments
+            .iter()
+            .find(|segment| segment.crossfade_ms > 0);
+        if let Some(segment) = crossfade {
+            if self.backend == MergeBackend::Native && format == AudioFormat::Mp3 {
+                return Err(anyhow!(
+                    "Episode segment {} crossfades, which the native merge can't do for mp3, \
+                     set MERGE_BACKEND=ffmpeg",
+                    segment.source
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+// EBU R128 normalization of the merged episodes
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct LoudnessConfig {
+    pub enabled: bool,
+    // Integrated loudness episodes are brought to, -16 is the usual podcast target
+    pub target_lufs: f64,
+    // The gain is lowered when it would push the true peak above this
+    pub true_peak_dbtp: f64,
+}
+
+impl Default for LoudnessConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            target_lufs: -16.0,
+            true_peak_dbtp: -1.0,
+        }
+    }
+}
+
+impl LoudnessConfig {
+    fn from_env() -> Result<Self> {
+        let defaults = Self::default();
+        Ok(Self {
+            enabled: env_or("LOUDNESS_NORMALIZE", defaults.enabled)?,
+            target_lufs: env_or("LOUDNESS_TARGET_LUFS", defaults.target_lufs)?,
+            true_peak_dbtp: env_or("LOUDNESS_TRUE_PEAK", defaults.true_peak_dbtp)?,
+        })
+    }
+}
+
+// ID3 tags and chapter markers written into the mp3 episodes
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct TagsConfig {
+    pub enabled: bool,
+    // Defaults to the name of the docs directory
+    pub album: Option<String>,
+    // Defaults to the conversation's speakers
+    pub artist: Option<String>,
+    // JPEG or PNG embedded as the front cover
+    pub cover: Option<PathBuf>,
+}
+
+impl Default for TagsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            album: None,
+            artist: None,
+            cover: None,
+        }
+    }
+}
+
+impl TagsConfig {
+    fn from_env() -> Result<Self> {
+        Ok(Self {
+            enabled: env_or("ID3_TAGS", true)?,
+            album: env_opt("ID3_ALBUM")?,
+            artist: env_opt("ID3_ARTIST")?,
+            cover: env_opt("ID3_COVER")?,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(from = "String")]
+pub enum SegmentSource {
+    // The spoken chapter intro, intro_N
+    Intro,
+    // The conversation audio, N
+    Body,
+    // A jingle or outro shared by every episode
+    File(PathBuf),
+}
+
+impl From<String> for SegmentSource {
+    fn from(value: String) -> Self {
+        match value.as_str() {
+            "intro" => SegmentSource::Intro,
+            "body" => SegmentSource::Body,
+            _ => SegmentSource::File(PathBuf::from(value)),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct SegmentConfig {
+    pub source: SegmentSource,
+    // Silence after the segment
+    #[serde(default)]
+    pub gap_ms: u64,
+    #[serde(default)]
+    pub fade_in_ms: u64,
+    #[serde(default)]
+    pub fade_out_ms: u64,
+    // Overlap with the end of the previous segment
+    #[serde(default)]
+    pub crossfade_ms: u64,
+}
+
+impl SegmentConfig {
+    fn new(source: SegmentSource) -> Self {
+        Self {
+            source,
+            gap_ms: 0,
+            fade_in_ms: 0,
+            fade_out_ms: 0,
+            crossfade_ms: 0,
+        }
+    }
+}
+
+impl fmt::Display for SegmentSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SegmentSource::Intro => f.write_str("intro"),
+            SegmentSource::Body => f.write_str("body"),
+            SegmentSource::File(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+// MERGE_SEGMENTS entries look like "jingle.mp3 fade_out=800 gap=300", durations in ms
+impl FromStr for SegmentConfig {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let mut parts = value.split_whitespace();
+        let source = parts
+            .next()
+            .ok_or_else(|| anyhow!("Empty MERGE_SEGMENTS entry"))?;
+        let mut segment = Self::new(SegmentSource::from(source.to_string()));
+
+        for option in parts {
+            let (key, ms) = option
+                .split_once('=')
+                .ok_or_else(|| anyhow!("Invalid MERGE_SEGMENTS option: {}", option))?;
+            let ms = ms
+                .parse()
+                .map_err(|_| anyhow!("Invalid duration in MERGE_SEGMENTS: {}", option))?;
+            match key {
+                "gap" => segment.gap_ms = ms,
+                "fade_in" => segment.fade_in_ms = ms,
+                "fade_out" => segment.fade_out_ms = ms,
+                "crossfade" => segment.crossfade_ms = ms,
+                _ => return Err(anyhow!("Unknown MERGE_SEGMENTS option: {}", key)),
+            }
+        }
+        Ok(segment)
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MergeBackend {
+    // In-process with symphonia, no external tools needed
+    #[default]
+    Native,
+    Ffmpeg,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AudioFormat {
+    #[default]
+    Mp3,
+    Wav,
+}
+
+impl AudioFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            AudioFormat::Mp3 => "mp3",
+            AudioFormat::Wav => "wav",
+        }
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            AudioFormat::Mp3 => "audio/mpeg",
+            AudioFormat::Wav => "audio/wav",
+        }
+    }
+}
+
+impl FromStr for TtsBackendType {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value.to_lowercase().as_str() {
+            "openai" => Ok(TtsBackendType::OpenAI),
+            "command" => Ok(TtsBackendType::Command),
+            _ => Err(anyhow!("Unknown TTS backend: {}", value)),
+        }
+    }
+}
+
+impl FromStr for MergeBackend {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value.to_lowercase().as_str() {
+            "native" => Ok(MergeBackend::Native),
+            "ffmpeg" => Ok(MergeBackend::Ffmpeg),
+            _ => Err(anyhow!("Unknown merge backend: {}", value)),
+        }
+    }
+}
+
+impl FromStr for AudioFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value.to_lowercase().as_str() {
+            "mp3" => Ok(AudioFormat::Mp3),
+            "wav" => Ok(AudioFormat::Wav),
+            _ => Err(anyhow!("Unknown audio format: {}", value)),
+        }
+    }
+}
+
+impl TtsConfig {
+    // Format of the clips the configured backend writes
+    pub fn output_format(&self) -> AudioFormat {
+        match self.backend {
+            TtsBackendType::OpenAI => AudioFormat::Mp3,
+            TtsBackendType::Command => self.format,
+        }
+    }
+
+    fn from_env() -> Result<Self> {
+        Ok(Self {
+            backend: env_or("TTS_BACKEND", TtsBackendType::default())?,
+            command: std::env::var("TTS_COMMAND").ok(),
+            format: env_or("TTS_FORMAT", AudioFormat::default())?,
+            max_chars_per_sec: env_or("TTS_MAX_CHARS_PER_SEC", default_max_chars_per_sec())?,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct VoiceConfig {
+    #[serde(default = "default_voice")]
+    pub default_voice: String,
+    #[serde(default)]
+    pub speakers: HashMap<String, String>,
+}
+
+fn default_voice() -> String {
+    String::from("alloy")
+}
+
+impl Default for VoiceConfig {
+    fn default() -> Self {
+        Self {
+            default_voice: default_voice(),
+            speakers: HashMap::from([
+                (String::from("Jaf"), String::from("onyx")),
+                (String::from("Paul"), String::from("nova")),
+            ]),
+        }
+    }
+}
+
+impl VoiceConfig {
+    // Speaker names are matched case-insensitively, unknown speakers get the default voice
+    pub fn voice_for(&self, speaker: Option<&str>) -> &str {
+        speaker
+            .and_then(|speaker| {
+                self.speakers
+                    .iter()
+                    .find(|(name, _)| name.eq_ignore_ascii_case(speaker))
+                    .map(|(_, voice)| voice.as_str())
+            })
+            .unwrap_or(&self.default_voice)
+    }
+
+    // Per-document voices win over the configured ones, "default" replaces the default voice
+    pub fn with_overrides(&self, overrides: &HashMap<String, String>) -> Self {
+        let mut voices = self.clone();
+        for (speaker, voice) in overrides {
+            if speaker.eq_ignore_ascii_case("default") {
+                voices.default_voice = voice.clone();
+            } else {
+                voices
+                    .speakers
+                    .retain(|name, _| !name.eq_ignore_ascii_case(speaker));
+                voices.speakers.insert(speaker.clone(), voice.clone());
+            }
+        }
+        voices
+    }
+
+    // TTS_VOICES is in the form "Jaf=onyx,Paul=nova"
+    fn from_env() -> Result<Self> {
+        let mut voices = Self::default();
+
+        if let Ok(default_voice) = std::env::var("TTS_DEFAULT_VOICE") {
+            voices.default_voice = default_voice;
+        }
+
+        if let Some(speakers) = env_pairs("TTS_VOICES")? {
+            voices.speakers = speakers;
+        }
+
+        Ok(voices)
+    }
+}
+
+// Setting any of these selects configuration from the environment
+const MODEL_VARS: [&str; 5] = [
+    "OPENAI_MODEL",
+    "OLLAMA_MODEL",
+    "ANTHROPIC_MODEL",
+    "GEMINI_MODEL",
+    "COMPATIBLE_MODEL",
+];
+
 impl Config {
     pub fn new() -> Result<Self> {
         dotenv().ok();
 
-        // First try to load from environment variables
-        if let Ok(config) = Self::from_env() {
-            return Ok(config);
+        // A model chosen in the environment means the environment is the configuration, and
+        // its errors are reported rather than hidden behind a search for a config file
+        if MODEL_VARS
+            .iter()
+            .any(|name| std::env::var_os(name).is_some())
+        {
+            return Self::from_env();
         }
 
-        // If env vars not set, try to load from config file
         Self::from_file()
     }
 
@@ -62,22 +827,48 @@ impl Config {
             ModelType::OpenAI(model)
         } else if let Ok(model) = std::env::var("OLLAMA_MODEL") {
             ModelType::Ollama(model)
+        } else if let Ok(model) = std::env::var("ANTHROPIC_MODEL") {
+            ModelType::Anthropic(model)
+        } else if let Ok(model) = std::env::var("GEMINI_MODEL") {
+            ModelType::Gemini(model)
+        } else if let Ok(model) = std::env::var("COMPATIBLE_MODEL") {
+            ModelType::Compatible(model)
         } else {
             return Err(anyhow!("No model configuration found in environment"));
         };
 
         Ok(Config {
             input: InputConfig {
-                docs_path: PathBuf::from(std::env::var("DOCS_PATH")?),
+                docs_path: PathBuf::from(env_required("DOCS_PATH")?),
+                include: env_list("DOCS_INCLUDE").unwrap_or_else(default_include),
+                exclude: env_list("DOCS_EXCLUDE").unwrap_or_else(default_exclude),
+                max_depth: env_opt("DOCS_MAX_DEPTH")?,
+                include_hidden: env_or("DOCS_INCLUDE_HIDDEN", false)?,
             },
             model: ModelConfig {
                 model_type,
                 openai_api_key: std::env::var("OPENAI_API_KEY").ok(),
+                openai_base_url: std::env::var("OPENAI_BASE_URL").ok(),
+                openai_headers: env_pairs("OPENAI_EXTRA_HEADERS")?.unwrap_or_default(),
                 ollama_base_url: std::env::var("OLLAMA_BASE_URL").ok(),
+                chunk_size: env_or("CHUNK_SIZE", default_chunk_size())?,
+                ollama: OllamaConfig::from_env()?,
+                anthropic: AnthropicConfig::from_env()?,
+                gemini: GeminiConfig::from_env(),
+                compatible: CompatibleConfig::from_env()?,
             },
             output: OutputConfig {
-                audio_path: PathBuf::from(std::env::var("AUDIO_OUTPUT_PATH")?),
+                audio_path: PathBuf::from(env_required("AUDIO_OUTPUT_PATH")?),
             },
+            voices: VoiceConfig::from_env()?,
+            tts: TtsConfig::from_env()?,
+            concurrency: ConcurrencyConfig::from_env()?,
+            retry: RetryConfig::from_env()?,
+            prompt: PromptConfig::from_env()?,
+            feed: FeedConfig::from_env()?,
+            merge: MergeConfig::from_env()?,
+            loudness: LoudnessConfig::from_env()?,
+            tags: TagsConfig::from_env()?,
         })
     }
 
@@ -97,3 +888,144 @@ impl Config {
         Err(anyhow!("No configuration file found"))
     }
 }
+
+fn env_required(name: &str) -> Result<String> {
+    std::env::var(name).map_err(|_| anyhow!("{} must be set", name))
+}
+
+// Reads an optional environment variable, failing only if it is set but malformed
+fn env_opt<T: FromStr>(name: &str) -> Result<Option<T>> {
+    match std::env::var(name) {
+        Ok(value) => value
+            .trim()
+            .parse()
+            .map(Some)
+            .map_err(|_| anyhow!("Invalid value for {}: {}", name, value)),
+        Err(_) => Ok(None),
+    }
+}
+
+// Reads "key=value,key=value" lists such as TTS_VOICES
+fn env_pairs(name: &str) -> Result<Option<HashMap<String, String>>> {
+    let Ok(value) = std::env::var(name) else {
+        return Ok(None);
+    };
+
+    let mut pairs = HashMap::new();
+    for pair in value.split(',').filter(|pair| !pair.trim().is_empty()) {
+        let (key, value) = pair
+            .split_once('=')
+            .ok_or_else(|| anyhow!("Invalid {} entry: {}", name, pair))?;
+        pairs.insert(key.trim().to_string(), value.trim().to_string());
+    }
+
+    Ok(Some(pairs))
+}
+
+// Reads comma separated lists such as DOCS_EXCLUDE, an empty value gives an empty list
+fn env_list(name: &str) -> Option<Vec<String>> {
+    let value = std::env::var(name).ok()?;
+    Some(
+        value
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(String::from)
+            .collect(),
+    )
+}
+
+fn env_or<T: FromStr>(name: &str, default: T) -> Result<T> {
+    Ok(env_opt(name)?.unwrap_or(default))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segments(value: &str) -> Vec<SegmentConfig> {
+        value
+            .split(',')
+            .map(|segment| segment.parse().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn parses_segment_options() {
+        let segment: SegmentConfig = "jingle.mp3 fade_out=800 gap=300 fade_in=20 crossfade=1500"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            segment.source,
+            SegmentSource::File(PathBuf::from("jingle.mp3"))
+        );
+        assert_eq!(
+            (
+                segment.gap_ms,
+                segment.fade_in_ms,
+                segment.fade_out_ms,
+                segment.crossfade_ms
+            ),
+            (300, 20, 800, 1500)
+        );
+
+        let segment: SegmentConfig = " intro ".parse().unwrap();
+        assert_eq!(segment.source, SegmentSource::Intro);
+        assert_eq!(segment.gap_ms, 0);
+        assert_eq!(
+            "body".parse::<SegmentConfig>().unwrap().source,
+            SegmentSource::Body
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_segments() {
+        for value in [
+            "",
+            "body gap",
+            "body gap=soon",
+            "body gap=-5",
+            "body loud=3",
+        ] {
+            assert!(value.parse::<SegmentConfig>().is_err(), "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn gap_before_a_crossfade_is_rejected() {
+        let merge = MergeConfig {
+            backend: MergeBackend::Ffmpeg,
+            segments: segments("intro gap=500, body crossfade=1000"),
+        };
+        let error = merge.check(AudioFormat::Mp3).unwrap_err().to_string();
+        assert!(error.contains("intro has a gap"), "{}", error);
+
+        let merge = MergeConfig {
+            backend: MergeBackend::Ffmpeg,
+            segments: segments("intro, body crossfade=1000 gap=500, outro.mp3"),
+        };
+        assert!(merge.check(AudioFormat::Mp3).is_ok());
+    }
+
+    #[test]
+    fn native_mp3_crossfades_are_rejected() {
+        let layout = segments("intro, body crossfade=1000");
+        let check = |backend, format| {
+            MergeConfig {
+                backend,
+                segments: layout.clone(),
+            }
+            .check(format)
+        };
+
+        let error = check(MergeBackend::Native, AudioFormat::Mp3).unwrap_err();
+        assert!(
+            error.to_string().contains("MERGE_BACKEND=ffmpeg"),
+            "{}",
+            error
+        );
+        assert!(check(MergeBackend::Native, AudioFormat::Wav).is_ok());
+        assert!(check(MergeBackend::Ffmpeg, AudioFormat::Mp3).is_ok());
+        assert!(MergeConfig::default().check(AudioFormat::Mp3).is_ok());
+    }
+}
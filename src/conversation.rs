@@ -1,92 +1,855 @@
-// Will implement conversation generation later
-
-use anyhow::Result;
+use anyhow::{anyhow, Context, Result};
 use async_trait::async_trait;
-use ollama_rs::{generation::completion::request::GenerationRequest, Ollama as OllamaRs};
+use minijinja::{context, Value};
+use ollama_rs::generation::options::GenerationOptions;
+use serde::{Deserialize, Serialize};
 use serde_json::json;
+use std::fmt;
+use std::path::Path;
 
-use crate::{config::ModelType, ConversationGeneration, ConversationGenerator};
+use crate::config::{Config, ModelType, PromptConfig};
+use crate::markdown::{self, FrontMatter};
+use crate::openai::OpenAIEndpoint;
+use crate::prompts::{self, PromptPreset};
+use crate::retry;
+use crate::stream::{self, PartialOutput, Progress};
+use crate::{manifest, ConversationGeneration, ConversationGenerator};
 
-pub struct ConversationPrompt {
+const DEFAULT_ANTHROPIC_BASE_URL: &str = "https://api.anthropic.com";
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+const DEFAULT_GEMINI_BASE_URL: &str = "https://generativelanguage.googleapis.com";
+
+// Longest name we accept in front of a colon as a speaker label
+const MAX_SPEAKER_NAME_LEN: usize = 30;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Conversation {
+    pub title: String,
+    pub participants: Vec<String>,
+    pub turns: Vec<Turn>,
+    // Where each generated segment starts, conversations saved before these were kept have none
+    #[serde(default)]
+    pub sections: Vec<Section>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Section {
+    pub title: String,
+    // Index of the section's first turn
+    pub first_turn: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Turn {
+    // None for narration that isn't attributed to any participant
+    pub speaker: Option<String>,
+    pub text: String,
+}
+
+impl Turn {
+    // "Jaf: Welcome to the show, today…", enough to tell turns apart in a chapter list
+    pub fn preview(&self, max_chars: usize) -> String {
+        let text = self.text.split_whitespace().collect::<Vec<_>>().join(" ");
+        let text = if text.chars().count() > max_chars {
+            let cut: String = text.chars().take(max_chars).collect();
+            format!("{}…", cut.trim_end())
+        } else {
+            text
+        };
+        match &self.speaker {
+            Some(speaker) => format!("{}: {}", speaker, text),
+            None => text,
+        }
+    }
+}
+
+impl Conversation {
+    // Split "Name: text" lines into turns, continuation lines belong to the previous speaker.
+    // Only known speakers start a turn, so "The key idea here: ..." stays part of the text.
+    pub fn parse(title: &str, text: &str, speakers: &[String]) -> Self {
+        let mut participants: Vec<String> = Vec::new();
+        let mut turns: Vec<Turn> = Vec::new();
+
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let label = parse_speaker(line).filter(|(speaker, _)| {
+                speakers
+                    .iter()
+                    .chain(&participants)
+                    .any(|name| name.eq_ignore_ascii_case(speaker))
+            });
+            match label {
+                Some((speaker, text)) => {
+                    if !participants.iter().any(|name| name == speaker) {
+                        participants.push(speaker.to_string());
+                    }
+                    turns.push(Turn {
+                        speaker: Some(speaker.to_string()),
+                        text: text.to_string(),
+                    });
+                }
+                None => match turns.last_mut() {
+                    Some(turn) => {
+                        turn.text.push('\n');
+                        turn.text.push_str(line);
+                    }
+                    None => turns.push(Turn {
+                        speaker: None,
+                        text: line.to_string(),
+                    }),
+                },
+            }
+        }
+
+        Self {
+            title: title.to_string(),
+            participants,
+            turns,
+            sections: Vec::new(),
+        }
+    }
+
+    pub fn new(title: &str) -> Self {
+        Self {
+            title: title.to_string(),
+            participants: Vec::new(),
+            turns: Vec::new(),
+            sections: Vec::new(),
+        }
+    }
+
+    // Append a segment generated from a later chunk of the same document
+    pub fn extend(&mut self, segment: Conversation) {
+        for participant in segment.participants {
+            if !self.participants.contains(&participant) {
+                self.participants.push(participant);
+            }
+        }
+        self.turns.extend(segment.turns);
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+impl fmt::Display for Conversation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, turn) in self.turns.iter().enumerate() {
+            if index > 0 {
+                writeln!(f)?;
+            }
+            match &turn.speaker {
+                Some(speaker) => writeln!(f, "{}: {}", speaker, turn.text)?,
+                None => writeln!(f, "{}", turn.text)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+// Recognizes "Jaf: ...", "**Jaf:** ..." and "**Jaf**: ..."
+fn parse_speaker(line: &str) -> Option<(&str, &str)> {
+    let (label, text) = line.split_once(':')?;
+    let speaker = label.trim_matches(|c: char| c == '*' || c == '_' || c.is_whitespace());
+
+    let is_name = !speaker.is_empty()
+        && speaker.len() <= MAX_SPEAKER_NAME_LEN
+        && speaker
+            .chars()
+            .all(|c| c.is_alphabetic() || c == ' ' || c == '.' || c == '-');
+    if !is_name {
+        return None;
+    }
+
+    let text = text.trim_start_matches(|c: char| c == '*' || c == '_' || c.is_whitespace());
+    Some((speaker, text))
+}
+
+// The document's own speakers, or the configured ones
+pub fn speaker_list(front_matter: &FrontMatter, config: &PromptConfig) -> Vec<String> {
+    if front_matter.speakers.is_empty() {
+        config.speakers.clone()
+    } else {
+        front_matter.speakers.clone()
+    }
+}
+
+// "Jaf", "Jaf and Paul", "Jaf, Paul and Ann"
+fn join_names(names: &[String]) -> String {
+    match names {
+        [] => String::new(),
+        [name] => name.clone(),
+        [rest @ .., last] => format!("{} and {}", rest.join(", "), last),
+    }
+}
+
+const SUMMARY_PROMPT: &str = "You keep a running summary of a conversation that is generated in parts. Given the summary so far and the newest part, reply with an updated summary of at most five sentences covering the topics already explained. Reply with the summary only.";
+
+// Exactly what a provider sends, every backend maps these onto its own system and user messages
+pub struct ChatPrompt {
     pub system: String,
     pub user: String,
 }
 
-impl Default for ConversationPrompt {
-    fn default() -> Self {
-        Self {
-            system: "You are an expert at converting technical documentation into natural conversations between a student and a teacher. Keep the technical accuracy but make it engaging and easier to understand. IMPORTANT: Output should have at most 4096 characters. It is also important to not include any json or code blocks in the output. ".into(),
-            user: "Convert the following markdown documentation into a natural conversation between two
-             Software Developers, first named Jaf is an expert in the protocol we are talking about,
-             a second named Paul is a frontend developer who is new to this protocol. 
-             Preserve all technical information but make it more engaging:".into(),
+// A preset rendered for one document, the user message is rendered per chunk
+pub struct ConversationPrompt {
+    pub system: String,
+    user: String,
+    context: PromptContext,
+}
+
+#[derive(Serialize)]
+struct PromptContext {
+    title: String,
+    // "Jaf and Paul", templates index speaker_list for roles
+    speakers: String,
+    speaker_list: Vec<String>,
+    target_length: usize,
+}
+
+impl ConversationPrompt {
+    pub fn new(
+        preset: &PromptPreset,
+        config: &PromptConfig,
+        title: &str,
+        front_matter: &FrontMatter,
+    ) -> Result<Self> {
+        let speaker_list = speaker_list(front_matter, config);
+        if speaker_list.is_empty() {
+            return Err(anyhow!(
+                "No speakers for {}, set PROMPT_SPEAKERS or speakers in its front matter",
+                title
+            ));
         }
+        let context = PromptContext {
+            title: title.to_string(),
+            speakers: join_names(&speaker_list),
+            speaker_list,
+            target_length: config.target_length,
+        };
+
+        let mut system = prompts::render(&preset.system, Value::from_serialize(&context))
+            .with_context(|| format!("Prompt preset {}", preset.name))?;
+        // Extra instructions from the document's front matter
+        if let Some(addendum) = front_matter.prompt.as_deref().map(str::trim) {
+            if !addendum.is_empty() {
+                system.push_str("\n\n");
+                system.push_str(addendum);
+            }
+        }
+
+        Ok(Self {
+            system,
+            user: preset.user.clone(),
+            context,
+        })
+    }
+
+    // Templates without {{ content }} get the chunk appended after the instructions
+    fn user_message(&self, content: &str) -> Result<String> {
+        let context = context! { content => content, ..Value::from_serialize(&self.context) };
+        let message = prompts::render(&self.user, context)?;
+        if prompts::uses_variable(&self.user, "content")? {
+            Ok(message)
+        } else {
+            Ok(format!("{}\n\n{}", message, content))
+        }
+    }
+
+    // Changes whenever the text sent for the same document would change
+    pub fn fingerprint(&self) -> Result<String> {
+        let context = serde_json::to_vec(&self.context)?;
+        Ok(manifest::hash(&[
+            self.system.as_bytes(),
+            self.user.as_bytes(),
+            &context,
+        ]))
+    }
+
+    // The one place the messages for a chunk are assembled, whichever provider sends them
+    fn messages(
+        &self,
+        part: usize,
+        parts: usize,
+        summary: &str,
+        chunk: &str,
+    ) -> Result<ChatPrompt> {
+        Ok(ChatPrompt {
+            system: self.for_part(part, parts, summary),
+            user: self.user_message(chunk)?,
+        })
+    }
+
+    // Tells the model where the chunk sits in the document so that segments join up
+    fn for_part(&self, part: usize, parts: usize, summary: &str) -> String {
+        if parts <= 1 {
+            return self.system.clone();
+        }
+
+        let mut system = format!(
+            "{}\n\nThe documentation is converted in {} parts and this is part {}.",
+            self.system,
+            parts,
+            part + 1
+        );
+        if part > 0 {
+            system
+                .push_str(" Continue the ongoing conversation without greetings or introductions.");
+        }
+        if part + 1 < parts {
+            system.push_str(
+                " Do not wrap up or say goodbye, the conversation continues in the next part.",
+            );
+        }
+        if !summary.is_empty() {
+            system.push_str(&format!(
+                "\n\nSummary of the conversation so far: {}",
+                summary
+            ));
+        }
+        system
     }
 }
 
 #[async_trait]
 impl ConversationGeneration for ConversationGenerator {
-    async fn generate_conversation(&self, content: &str) -> Result<String> {
-        let prompt = ConversationPrompt::default();
+    async fn generate_conversation(
+        &self,
+        prompt: &ConversationPrompt,
+        title: &str,
+        content: &str,
+        partial: &mut PartialOutput,
+    ) -> Result<Conversation> {
+        let chunks = markdown::split_into_chunks(content, self.chunk_size);
+
+        let mut conversation = Conversation::new(title);
+        let mut summary = String::new();
+
+        for (index, chunk) in chunks.iter().enumerate() {
+            if chunks.len() > 1 {
+                println!("Generating segment {}/{}", index + 1, chunks.len());
+            }
+
+            let label = if chunks.len() > 1 {
+                format!("{} {}/{}", title, index + 1, chunks.len())
+            } else {
+                title.to_string()
+            };
+            if index > 0 {
+                partial.push("\n\n")?;
+            }
+
+            let messages = prompt.messages(index, chunks.len(), &summary, chunk)?;
+            let answer = self
+                .complete(&messages, &label, Some(&mut *partial))
+                .await?;
+
+            // Carry a short summary forward so the next segment knows what was covered
+            if index + 1 < chunks.len() {
+                let update = ChatPrompt {
+                    system: SUMMARY_PROMPT.to_string(),
+                    user: format!("Summary so far:\n{}\n\nNewest part:\n{}", summary, answer),
+                };
+                summary = self
+                    .complete(&update, &format!("{} summary", label), None)
+                    .await?;
+            }
+
+            // Named after the chunk's first heading, used for the episode's chapter markers
+            conversation.sections.push(Section {
+                title: markdown::find_heading(chunk)
+                    .unwrap_or_else(|| format!("Part {}", index + 1)),
+                first_turn: conversation.turns.len(),
+            });
+            conversation.extend(Conversation::parse(
+                title,
+                &answer,
+                &prompt.context.speaker_list,
+            ));
+        }
+
+        Ok(conversation)
+    }
+}
+
+impl ConversationGenerator {
+    // Fails early when the chosen provider is missing its key or server
+    pub fn new(config: &Config, model_type: ModelType) -> Result<Self> {
+        let model = &config.model;
+        let openai = match &model_type {
+            ModelType::Compatible(_) => {
+                OpenAIEndpoint::compatible(&model.compatible).ok_or_else(|| {
+                    anyhow!("COMPATIBLE_BASE_URL is required for the compatible provider")
+                })?
+            }
+            _ => OpenAIEndpoint::from_config(model),
+        };
+
+        let required_key = match &model_type {
+            ModelType::OpenAI(_) if openai.is_official() => {
+                Some(("OPENAI_API_KEY", &openai.api_key))
+            }
+            ModelType::Anthropic(_) if model.anthropic.base_url.is_none() => {
+                Some(("ANTHROPIC_API_KEY", &model.anthropic.api_key))
+            }
+            ModelType::Gemini(_) if model.gemini.base_url.is_none() => {
+                Some(("GEMINI_API_KEY", &model.gemini.api_key))
+            }
+            _ => None,
+        };
+        if let Some((variable, None)) = required_key {
+            return Err(anyhow!(
+                "{} is required for conversation generation",
+                variable
+            ));
+        }
+
+        Ok(Self {
+            live_progress: config.concurrency.llm(&model_type) == 1,
+            model_type,
+            openai,
+            anthropic: model.anthropic.clone(),
+            gemini: model.gemini.clone(),
+            ollama_url: model
+                .ollama_base_url
+                .clone()
+                .unwrap_or_else(|| String::from("http://localhost:11434")),
+            ollama_options: model.ollama.clone(),
+            chunk_size: model.chunk_size,
+            retry: config.retry.clone(),
+            preset: PromptPreset::load(&config.prompt)?,
+            prompt: config.prompt.clone(),
+        })
+    }
+
+    pub fn prompt_for(
+        &self,
+        title: &str,
+        front_matter: &FrontMatter,
+    ) -> Result<ConversationPrompt> {
+        ConversationPrompt::new(&self.preset, &self.prompt, title, front_matter)
+    }
+
+    // Provider and endpoint, the same model name can be a different model elsewhere
+    pub fn provider(&self) -> String {
+        let (kind, endpoint) = match &self.model_type {
+            ModelType::Ollama(_) => ("ollama", self.ollama_url.as_str()),
+            ModelType::OpenAI(_) => ("openai", self.openai.base_url.as_str()),
+            ModelType::Compatible(_) => ("compatible", self.openai.base_url.as_str()),
+            ModelType::Anthropic(_) => (
+                "anthropic",
+                self.anthropic
+                    .base_url
+                    .as_deref()
+                    .unwrap_or(DEFAULT_ANTHROPIC_BASE_URL),
+            ),
+            ModelType::Gemini(_) => (
+                "gemini",
+                self.gemini
+                    .base_url
+                    .as_deref()
+                    .unwrap_or(DEFAULT_GEMINI_BASE_URL),
+            ),
+        };
+        format!("{} {}", kind, endpoint.trim_end_matches('/'))
+    }
+
+    fn ollama_generation_options(&self) -> GenerationOptions {
+        let mut options = GenerationOptions::default();
+        if let Some(temperature) = self.ollama_options.temperature {
+            options = options.temperature(temperature);
+        }
+        if let Some(num_ctx) = self.ollama_options.num_ctx {
+            options = options.num_ctx(num_ctx);
+        }
+        if let Some(seed) = self.ollama_options.seed {
+            options = options.seed(seed);
+        }
+        options
+    }
+
+    // Streamed text goes to the partial output as it arrives, summaries pass None
+    async fn complete(
+        &self,
+        prompt: &ChatPrompt,
+        label: &str,
+        mut partial: Option<&mut PartialOutput>,
+    ) -> Result<String> {
+        let mut on_text = |text: &str| match partial.as_deref_mut() {
+            Some(partial) => partial.push(text),
+            None => Ok(()),
+        };
 
         match &self.model_type {
             ModelType::Ollama(model) => {
-                println!("Making Ollama API call...");
-                let ollama = OllamaRs::default();
-                let request = GenerationRequest::new(model.clone(), content.to_string())
-                    .system(prompt.system);
-
-                println!("Sending request to Ollama...");
-                match ollama.generate(request).await {
-                    Ok(response) => {
-                        println!("Received response from Ollama");
-                        Ok(response.response)
-                    }
-                    Err(e) => {
-                        println!("Error from Ollama: {}", e);
-                        Err(e.into())
-                    }
-                }
+                self.stream_ollama(model, prompt, label, &mut on_text).await
             }
             ModelType::OpenAI(model) => {
-                println!("Making OpenAI API call...");
-                let client = reqwest::Client::new();
-
-                let payload = json!({
-                    "model": model,
-                    "messages": [
-                        {
-                            "role": "system",
-                            "content": prompt.system
-                        },
-                        {
-                            "role": "user",
-                            "content": format!("{}\n\n{}", prompt.user, content)
-                        }
-                    ],
-                    "temperature": 0.7,
-                    "max_tokens": 2000
-                });
-
-                println!("Sending request to OpenAI...");
-                let response: serde_json::Value = client
-                    .post("https://api.openai.com/v1/chat/completions")
-                    .header("Authorization", format!("Bearer {}", self.api_key))
-                    .header("Content-Type", "application/json")
-                    .json(&payload)
-                    .send()
-                    .await?
-                    .json()
-                    .await?;
+                self.stream_openai(model, prompt, label, "OpenAI", &mut on_text)
+                    .await
+            }
+            ModelType::Compatible(model) => {
+                self.stream_openai(
+                    model,
+                    prompt,
+                    label,
+                    "OpenAI-compatible server",
+                    &mut on_text,
+                )
+                .await
+            }
+            ModelType::Anthropic(model) => {
+                let answer = self.complete_anthropic(model, prompt).await?;
+                on_text(&answer)?;
+                Ok(answer)
+            }
+            ModelType::Gemini(model) => {
+                let answer = self.complete_gemini(model, prompt).await?;
+                on_text(&answer)?;
+                Ok(answer)
+            }
+        }
+    }
 
-                let answer = response["choices"][0]["message"]["content"]
-                    .as_str()
-                    .ok_or_else(|| anyhow::anyhow!("Invalid response from OpenAI"))?
-                    .to_string();
+    // /api/chat streams one JSON object per line until one has done set
+    async fn stream_ollama(
+        &self,
+        model: &str,
+        prompt: &ChatPrompt,
+        label: &str,
+        on_text: &mut (dyn FnMut(&str) -> Result<()> + Send),
+    ) -> Result<String> {
+        let client = reqwest::Client::new();
+        let url = format!("{}/api/chat", self.ollama_url.trim_end_matches('/'));
+        let payload = json!({
+            "model": model,
+            "messages": [
+                {
+                    "role": "system",
+                    "content": prompt.system
+                },
+                {
+                    "role": "user",
+                    "content": prompt.user
+                }
+            ],
+            "options": self.ollama_generation_options(),
+            "stream": true
+        });
 
-                println!("Received response from OpenAI");
-                Ok(answer)
+        // OLLAMA_TIMEOUT_SECS covers slow local models, both for the first byte and between tokens
+        let mut retry_config = self.retry.clone();
+        if let Some(timeout_secs) = self.ollama_options.timeout_secs {
+            retry_config.timeout_secs = timeout_secs;
+        }
+
+        println!("Sending request to Ollama at {}...", self.ollama_url);
+        let response = retry::open(&retry_config, "Ollama request", || {
+            client.post(&url).json(&payload)
+        })
+        .await
+        .inspect_err(|e| println!("Error from Ollama: {:#}", e))?;
+
+        let mut progress = Progress::new(label, self.live_progress);
+        let mut answer = String::new();
+        stream::for_each_line(response, retry_config.timeout(), |line| {
+            if line.is_empty() {
+                return Ok(false);
+            }
+            let chunk: serde_json::Value = serde_json::from_str(line)
+                .with_context(|| format!("Invalid stream line from Ollama: {}", line))?;
+            if let Some(error) = chunk["error"].as_str() {
+                return Err(anyhow!("Ollama: {}", error));
+            }
+            if let Some(text) = chunk["message"]["content"].as_str() {
+                if !text.is_empty() {
+                    progress.token();
+                    on_text(text)?;
+                    answer.push_str(text);
+                }
+            }
+            Ok(chunk["done"].as_bool().unwrap_or(false))
+        })
+        .await
+        .context("Ollama stream failed")?;
+
+        progress.finish();
+        Ok(answer)
+    }
+
+    // Chat completions for OpenAI and the compatible provider, self.openai points at either.
+    // Streamed as server-sent events, each data line carries a delta until [DONE].
+    async fn stream_openai(
+        &self,
+        model: &str,
+        prompt: &ChatPrompt,
+        label: &str,
+        name: &str,
+        on_text: &mut (dyn FnMut(&str) -> Result<()> + Send),
+    ) -> Result<String> {
+        let client = reqwest::Client::new();
+
+        let payload = json!({
+            "model": model,
+            "messages": [
+                {
+                    "role": "system",
+                    "content": prompt.system
+                },
+                {
+                    "role": "user",
+                    "content": prompt.user
+                }
+            ],
+            "temperature": 0.7,
+            "max_tokens": 2000,
+            "stream": true
+        });
+
+        println!("Sending request to {}...", name);
+        let response = retry::open(&self.retry, &format!("{} chat completion", name), || {
+            self.openai
+                .post(&client, "/chat/completions")
+                .header("Content-Type", "application/json")
+                .json(&payload)
+        })
+        .await?;
+
+        let mut progress = Progress::new(label, self.live_progress);
+        let mut answer = String::new();
+        stream::for_each_line(response, self.retry.timeout(), |line| {
+            // Blank separators, comments and event names carry no text
+            let Some(data) = line.strip_prefix("data:").map(str::trim) else {
+                return Ok(false);
+            };
+            if data == "[DONE]" {
+                return Ok(true);
+            }
+            let event: serde_json::Value = serde_json::from_str(data)
+                .with_context(|| format!("Invalid stream event from {}: {}", name, data))?;
+            if let Some(error) = event["error"]["message"].as_str() {
+                return Err(anyhow!("{}: {}", name, error));
+            }
+            if let Some(text) = event["choices"][0]["delta"]["content"].as_str() {
+                if !text.is_empty() {
+                    progress.token();
+                    on_text(text)?;
+                    answer.push_str(text);
+                }
+            }
+            Ok(false)
+        })
+        .await
+        .with_context(|| format!("{} stream failed", name))?;
+
+        progress.finish();
+        Ok(answer)
+    }
+
+    async fn complete_anthropic(&self, model: &str, prompt: &ChatPrompt) -> Result<String> {
+        println!("Making Anthropic API call...");
+        let client = reqwest::Client::new();
+        let base_url = self
+            .anthropic
+            .base_url
+            .as_deref()
+            .unwrap_or(DEFAULT_ANTHROPIC_BASE_URL)
+            .trim_end_matches('/');
+
+        let payload = json!({
+            "model": model,
+            "max_tokens": self.anthropic.max_tokens,
+            "system": prompt.system,
+            "messages": [
+                {
+                    "role": "user",
+                    "content": prompt.user
+                }
+            ],
+            "temperature": 0.7
+        });
+
+        let body = retry::send(&self.retry, "Anthropic message", || {
+            let mut request = client
+                .post(format!("{}/v1/messages", base_url))
+                .header("anthropic-version", ANTHROPIC_VERSION)
+                .json(&payload);
+            if let Some(api_key) = &self.anthropic.api_key {
+                request = request.header("x-api-key", api_key);
+            }
+            request
+        })
+        .await?;
+        let response: serde_json::Value = serde_json::from_slice(&body)?;
+
+        // Replies are a list of content blocks, only the text blocks belong to the dialogue
+        let blocks = response["content"]
+            .as_array()
+            .ok_or_else(|| anyhow::anyhow!("Invalid response from Anthropic"))?;
+        let answer: String = blocks
+            .iter()
+            .filter(|block| block["type"] == "text")
+            .filter_map(|block| block["text"].as_str())
+            .collect();
+
+        println!("Received response from Anthropic");
+        Ok(answer)
+    }
+
+    async fn complete_gemini(&self, model: &str, prompt: &ChatPrompt) -> Result<String> {
+        println!("Making Gemini API call...");
+        let client = reqwest::Client::new();
+        let base_url = self
+            .gemini
+            .base_url
+            .as_deref()
+            .unwrap_or(DEFAULT_GEMINI_BASE_URL)
+            .trim_end_matches('/');
+
+        let payload = json!({
+            "systemInstruction": {
+                "parts": [{ "text": prompt.system }]
+            },
+            "contents": [
+                {
+                    "role": "user",
+                    "parts": [{ "text": prompt.user }]
+                }
+            ],
+            "generationConfig": {
+                "temperature": 0.7
+            }
+        });
+
+        let body = retry::send(&self.retry, "Gemini generation", || {
+            let mut request = client
+                .post(format!(
+                    "{}/v1beta/models/{}:generateContent",
+                    base_url, model
+                ))
+                .json(&payload);
+            if let Some(api_key) = &self.gemini.api_key {
+                request = request.header("x-goog-api-key", api_key);
+            }
+            request
+        })
+        .await?;
+        let response: serde_json::Value = serde_json::from_slice(&body)?;
+
+        let parts = response["candidates"][0]["content"]["parts"]
+            .as_array()
+            .ok_or_else(|| {
+                // A blocked prompt comes back without candidates but with a reason
+                match response["promptFeedback"]["blockReason"].as_str() {
+                    Some(reason) => anyhow::anyhow!("Gemini blocked the prompt: {}", reason),
+                    None => anyhow::anyhow!("Invalid response from Gemini"),
+                }
+            })?;
+        let answer: String = parts
+            .iter()
+            .filter_map(|part| part["text"].as_str())
+            .collect();
+
+        println!("Received response from Gemini");
+        Ok(answer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn speakers() -> Vec<String> {
+        vec!["Jaf".to_string(), "Paul".to_string()]
+    }
+
+    #[test]
+    fn parses_plain_and_bold_labels() {
+        let text = "Jaf: Welcome back.\n\n**Paul:** Thanks.\n**Jaf**: Let's start.";
+        let conversation = Conversation::parse("1", text, &speakers());
+
+        assert_eq!(conversation.participants, ["Jaf", "Paul"]);
+        let turns: Vec<_> = conversation
+            .turns
+            .iter()
+            .map(|turn| (turn.speaker.as_deref(), turn.text.as_str()))
+            .collect();
+        assert_eq!(
+            turns,
+            [
+                (Some("Jaf"), "Welcome back."),
+                (Some("Paul"), "Thanks."),
+                (Some("Jaf"), "Let's start."),
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_labels_continue_the_previous_turn() {
+        let text = "Jaf: Relays are simple.\nThe key idea here: clients pick relays.\nPaul: Right.";
+        let conversation = Conversation::parse("1", text, &speakers());
+
+        assert_eq!(conversation.participants, ["Jaf", "Paul"]);
+        assert_eq!(conversation.turns.len(), 2);
+        assert_eq!(
+            conversation.turns[0].text,
+            "Relays are simple.\nThe key idea here: clients pick relays."
+        );
+    }
+
+    #[test]
+    fn speaker_names_match_case_insensitively() {
+        let conversation = Conversation::parse("1", "JAF: Hi.\npaul: Hello.", &speakers());
+        assert_eq!(conversation.participants, ["JAF", "paul"]);
+    }
+
+    #[test]
+    fn text_before_the_first_label_is_narration() {
+        let text = "Chapter 1. Relays: the basics.\nJaf: Hi.";
+        let conversation = Conversation::parse("1", text, &speakers());
+
+        assert_eq!(conversation.turns[0].speaker, None);
+        assert_eq!(conversation.turns[0].text, "Chapter 1. Relays: the basics.");
+        assert_eq!(conversation.turns[1].speaker.as_deref(), Some("Jaf"));
+    }
+
+    #[test]
+    fn builtin_presets_render_for_one_or_two_speakers() {
+        for preset in ["conversation", "interview", "debate", "lecture"] {
+            for speakers in [vec!["Jaf"], vec!["Jaf", "Paul"]] {
+                let config = PromptConfig {
+                    preset: preset.to_string(),
+                    speakers: speakers.iter().map(|name| name.to_string()).collect(),
+                    ..PromptConfig::default()
+                };
+                let preset = PromptPreset::load(&config).unwrap();
+                let prompt =
+                    ConversationPrompt::new(&preset, &config, "Relays", &FrontMatter::default())
+                        .unwrap();
+                let user = prompt.user_message("Relays store events.").unwrap();
+
+                assert!(user.contains("Jaf"), "{}", user);
+                assert_eq!(user.contains("Paul"), speakers.len() > 1, "{}", user);
+                assert!(user.contains("Relays store events."), "{}", user);
             }
         }
     }
+
+    #[test]
+    fn no_known_speakers_means_no_turns_split() {
+        let conversation = Conversation::parse("1", "Jaf: Hi.\nPaul: Hello.", &[]);
+        assert!(conversation.participants.is_empty());
+        assert_eq!(conversation.turns.len(), 1);
+    }
 }
@@ -0,0 +1,166 @@
+use anyhow::{anyhow, Context, Result};
+use reqwest::Url;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::config::{AudioFormat, FeedConfig};
+
+pub const FEED_FILE: &str = "feed.xml";
+
+// One merged chapter as it appears in the feed
+pub struct Episode {
+    // Stable across reruns so podcast apps don't list a regenerated episode twice
+    pub guid: String,
+    pub title: String,
+    pub description: String,
+    pub number: Option<u32>,
+    pub file: PathBuf,
+    pub size: u64,
+    pub duration: Duration,
+    pub published: SystemTime,
+}
+
+// Writes an RSS 2.0 feed with iTunes tags to output_dir/feed.xml
+pub fn write(
+    config: &FeedConfig,
+    format: AudioFormat,
+    episodes: &[Episode],
+    output_dir: &Path,
+) -> Result<PathBuf> {
+    let base_url = config
+        .base_url
+        .as_deref()
+        .ok_or_else(|| anyhow!("FEED_BASE_URL is required to write the podcast feed"))?;
+    // Without the trailing slash the last path segment would be replaced on join
+    let base_url = Url::parse(&format!("{}/", base_url.trim_end_matches('/')))
+        .with_context(|| format!("Invalid FEED_BASE_URL {}", base_url))?;
+
+    let mut xml = String::new();
+    writeln!(xml, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        xml,
+        r#"<rss version="2.0" xmlns:itunes="http://www.itunes.com/dtds/podcast-1.0.dtd" xmlns:atom="http://www.w3.org/2005/Atom">"#
+    )?;
+    writeln!(xml, "  <channel>")?;
+    writeln!(xml, "    <title>{}</title>", escape(&config.title))?;
+    writeln!(xml, "    <link>{}</link>", escape(base_url.as_str()))?;
+    writeln!(
+        xml,
+        r#"    <atom:link href="{}" rel="self" type="application/rss+xml"/>"#,
+        escape(base_url.join(FEED_FILE)?.as_str())
+    )?;
+    writeln!(
+        xml,
+        "    <description>{}</description>",
+        escape(&config.description)
+    )?;
+    writeln!(xml, "    <language>{}</language>", escape(&config.language))?;
+    writeln!(
+        xml,
+        "    <lastBuildDate>{}</lastBuildDate>",
+        httpdate::fmt_http_date(SystemTime::now())
+    )?;
+    writeln!(xml, "    <itunes:type>episodic</itunes:type>")?;
+    writeln!(
+        xml,
+        "    <itunes:summary>{}</itunes:summary>",
+        escape(&config.description)
+    )?;
+    if let Some(author) = &config.author {
+        writeln!(xml, "    <itunes:author>{}</itunes:author>", escape(author))?;
+    }
+    if let Some(image) = &config.image {
+        writeln!(xml, r#"    <itunes:image href="{}"/>"#, escape(image))?;
+    }
+    if let Some(category) = &config.category {
+        writeln!(xml, r#"    <itunes:category text="{}"/>"#, escape(category))?;
+    }
+    writeln!(
+        xml,
+        "    <itunes:explicit>{}</itunes:explicit>",
+        config.explicit
+    )?;
+
+    for episode in episodes {
+        let file_name = episode
+            .file
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| anyhow!("Invalid episode file {}", episode.file.display()))?;
+        let url = base_url.join(file_name)?;
+
+        writeln!(xml, "    <item>")?;
+        writeln!(xml, "      <title>{}</title>", escape(&episode.title))?;
+        writeln!(
+            xml,
+            "      <description>{}</description>",
+            escape(&episode.description)
+        )?;
+        writeln!(
+            xml,
+            "      <itunes:summary>{}</itunes:summary>",
+            escape(&episode.description)
+        )?;
+        writeln!(
+            xml,
+            r#"      <enclosure url="{}" length="{}" type="{}"/>"#,
+            escape(url.as_str()),
+            episode.size,
+            format.mime_type()
+        )?;
+        writeln!(
+            xml,
+            r#"      <guid isPermaLink="false">{}</guid>"#,
+            escape(&episode.guid)
+        )?;
+        writeln!(
+            xml,
+            "      <pubDate>{}</pubDate>",
+            httpdate::fmt_http_date(episode.published)
+        )?;
+        writeln!(
+            xml,
+            "      <itunes:duration>{}</itunes:duration>",
+            itunes_duration(episode.duration)
+        )?;
+        if let Some(number) = episode.number {
+            writeln!(xml, "      <itunes:episode>{}</itunes:episode>", number)?;
+        }
+        writeln!(xml, "      <itunes:episodeType>full</itunes:episodeType>")?;
+        writeln!(xml, "    </item>")?;
+    }
+
+    writeln!(xml, "  </channel>")?;
+    writeln!(xml, "</rss>")?;
+
+    let path = output_dir.join(FEED_FILE);
+    std::fs::write(&path, xml)?;
+    Ok(path)
+}
+
+// HH:MM:SS, rounded to the nearest second
+fn itunes_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs_f64().round() as u64;
+    format!(
+        "{:02}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,391 @@
+// ID3v2.3 tags with the chapter addendum (CHAP and CTOC frames) so podcast players show the
+// episode's metadata and can list and skip between its parts. v2.3 is what most players read.
+
+use anyhow::{anyhow, Result};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// A CTOC frame counts its children in one byte
+const MAX_TOC_CHILDREN: usize = 255;
+
+pub struct Tag {
+    pub title: String,
+    pub album: Option<String>,
+    pub artist: Option<String>,
+    pub track: Option<u32>,
+    pub date: SystemTime,
+    pub cover: Option<Picture>,
+    pub chapters: Vec<TocEntry>,
+}
+
+#[derive(Clone)]
+pub struct Picture {
+    pub mime_type: &'static str,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub struct Chapter {
+    pub title: String,
+    pub start: Duration,
+    pub end: Duration,
+}
+
+// A chapter, or a titled group of chapters with its own table of contents
+#[derive(Debug)]
+pub enum TocEntry {
+    Chapter(Chapter),
+    Group {
+        title: String,
+        chapters: Vec<Chapter>,
+    },
+}
+
+impl Picture {
+    pub fn load(path: &Path) -> Result<Self> {
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(str::to_lowercase);
+        let mime_type = match extension.as_deref() {
+            Some("jpg" | "jpeg") => "image/jpeg",
+            Some("png") => "image/png",
+            _ => {
+                return Err(anyhow!(
+                    "Cover art {} must be a JPEG or PNG",
+                    path.display()
+                ))
+            }
+        };
+        Ok(Self {
+            mime_type,
+            data: std::fs::read(path)?,
+        })
+    }
+}
+
+// The file without its leading ID3v2 tags, what a rewritten tag goes in front of
+pub fn audio_data(mut bytes: &[u8]) -> &[u8] {
+    while bytes.len() >= 10 && bytes.starts_with(b"ID3") {
+        let footer = if bytes[5] & 0x10 != 0 { 10 } else { 0 };
+        let len = 10 + syncsafe_decode(&bytes[6..10]) as usize + footer;
+        bytes = &bytes[len.min(bytes.len())..];
+    }
+    bytes
+}
+
+// Replaces any tag already at the start of the file
+pub fn write(path: &Path, tag: &Tag) -> Result<()> {
+    let file = std::fs::read(path)?;
+
+    let mut frames = Vec::new();
+    frames.extend(text_frame(b"TIT2", &tag.title));
+    if let Some(album) = &tag.album {
+        frames.extend(text_frame(b"TALB", album));
+    }
+    if let Some(artist) = &tag.artist {
+        frames.extend(text_frame(b"TPE1", artist));
+    }
+    if let Some(track) = tag.track {
+        frames.extend(text_frame(b"TRCK", &track.to_string()));
+    }
+    let (year, month, day) = civil_date(tag.date);
+    frames.extend(text_frame(b"TYER", &format!("{:04}", year)));
+    frames.extend(text_frame(b"TDAT", &format!("{:02}{:02}", day, month)));
+    if let Some(cover) = &tag.cover {
+        frames.extend(picture_frame(cover));
+    }
+    frames.extend(chapter_frames(&tag.chapters));
+
+    let mut tagged = Vec::with_capacity(10 + frames.len() + file.len());
+    tagged.extend_from_slice(b"ID3\x03\x00\x00");
+    tagged.extend_from_slice(&syncsafe_encode(frames.len() as u32));
+    tagged.extend(frames);
+    tagged.extend_from_slice(audio_data(&file));
+    std::fs::write(path, tagged)?;
+    Ok(())
+}
+
+// A top-level table of contents over the entries, and one per group over its chapters
+fn chapter_frames(entries: &[TocEntry]) -> Vec<u8> {
+    let mut frames = Vec::new();
+    if entries.is_empty() {
+        return frames;
+    }
+
+    let mut next_chapter = 0;
+    let mut chapter = |frames: &mut Vec<u8>, chapter: &Chapter| {
+        let id = format!("chp{}", next_chapter);
+        next_chapter += 1;
+        frames.extend(chap_frame(&id, chapter));
+        id
+    };
+
+    let mut children = Vec::new();
+    for (index, entry) in entries.iter().enumerate() {
+        match entry {
+            TocEntry::Chapter(entry) => children.push(chapter(&mut frames, entry)),
+            TocEntry::Group { title, chapters } => {
+                let ids: Vec<String> = chapters
+                    .iter()
+                    .map(|entry| chapter(&mut frames, entry))
+                    .collect();
+                let id = format!("toc{}", index);
+                toc_frames(&mut frames, &id, false, &ids, title);
+                children.push(id);
+            }
+        }
+    }
+    toc_frames(&mut frames, "toc", true, &children, "Chapters");
+    frames
+}
+
+// Longer lists than one CTOC can hold are split into numbered tables of contents under it
+fn toc_frames(frames: &mut Vec<u8>, id: &str, top_level: bool, children: &[String], title: &str) {
+    if children.len() <= MAX_TOC_CHILDREN {
+        frames.extend(ctoc_frame(id, top_level, children, title));
+        return;
+    }
+
+    let parts = children.len().div_ceil(MAX_TOC_CHILDREN);
+    let ids: Vec<String> = children
+        .chunks(MAX_TOC_CHILDREN)
+        .enumerate()
+        .map(|(part, chunk)| {
+            let part_id = format!("{}.{}", id, part);
+            let part_title = format!("{} ({}/{})", title, part + 1, parts);
+            toc_frames(frames, &part_id, false, chunk, &part_title);
+            part_id
+        })
+        .collect();
+    toc_frames(frames, id, top_level, &ids, title);
+}
+
+fn chap_frame(id: &str, chapter: &Chapter) -> Vec<u8> {
+    let mut body = latin1(id);
+    for time in [chapter.start, chapter.end] {
+        body.extend_from_slice(&(time.as_millis() as u32).to_be_bytes());
+    }
+    // Byte offsets are unused, players seek by time
+    body.extend_from_slice(&[0xFF; 8]);
+    body.extend(text_frame(b"TIT2", &chapter.title));
+    frame(b"CHAP", body)
+}
+
+fn ctoc_frame(id: &str, top_level: bool, children: &[String], title: &str) -> Vec<u8> {
+    let mut body = latin1(id);
+    // Ordered, and the top-level flag for the root of the tree
+    body.push(if top_level { 0b11 } else { 0b01 });
+    body.push(children.len() as u8);
+    for child in children {
+        body.extend(latin1(child));
+    }
+    body.extend(text_frame(b"TIT2", title));
+    frame(b"CTOC", body)
+}
+
+fn picture_frame(picture: &Picture) -> Vec<u8> {
+    let mut body = vec![0];
+    body.extend(latin1(picture.mime_type));
+    // Front cover, empty description
+    body.extend_from_slice(&[0x03, 0x00]);
+    body.extend_from_slice(&picture.data);
+    frame(b"APIC", body)
+}
+
+fn text_frame(id: &[u8; 4], text: &str) -> Vec<u8> {
+    frame(id, encode(text))
+}
+
+fn frame(id: &[u8; 4], body: Vec<u8>) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(10 + body.len());
+    frame.extend_from_slice(id);
+    frame.extend_from_slice(&(body.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&[0, 0]);
+    frame.extend(body);
+    frame
+}
+
+// ISO-8859-1 when every character fits, UTF-16 with a byte order mark otherwise
+fn encode(text: &str) -> Vec<u8> {
+    if text.chars().all(|c| (c as u32) < 0x100) {
+        let mut bytes = vec![0];
+        bytes.extend(text.chars().map(|c| c as u8));
+        bytes
+    } else {
+        let mut bytes = vec![1, 0xFF, 0xFE];
+        bytes.extend(text.encode_utf16().flat_map(u16::to_le_bytes));
+        bytes
+    }
+}
+
+// Null-terminated, for element IDs and MIME types which are plain ASCII
+fn latin1(text: &str) -> Vec<u8> {
+    let mut bytes = text.as_bytes().to_vec();
+    bytes.push(0);
+    bytes
+}
+
+// Tag sizes use 7 bits per byte so they never look like an mp3 frame sync
+fn syncsafe_encode(value: u32) -> [u8; 4] {
+    [21, 14, 7, 0].map(|shift| ((value >> shift) & 0x7F) as u8)
+}
+
+fn syncsafe_decode(bytes: &[u8]) -> u32 {
+    bytes
+        .iter()
+        .fold(0, |value, byte| (value << 7) | (*byte & 0x7F) as u32)
+}
+
+// UTC year, month and day, from days since the epoch as in Howard Hinnant's civil_from_days
+fn civil_date(time: SystemTime) -> (i64, u32, u32) {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default();
+    let days = (secs / 86_400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Frame IDs and bodies, one after the other
+    fn frames(mut bytes: &[u8]) -> Vec<(String, Vec<u8>)> {
+        let mut frames = Vec::new();
+        while bytes.len() >= 10 {
+            let id = String::from_utf8(bytes[..4].to_vec()).unwrap();
+            let len = u32::from_be_bytes(bytes[4..8].try_into().unwrap()) as usize;
+            frames.push((id, bytes[10..10 + len].to_vec()));
+            bytes = &bytes[10 + len..];
+        }
+        frames
+    }
+
+    fn tag_frames(file: &[u8]) -> Vec<(String, Vec<u8>)> {
+        frames(&file[10..10 + syncsafe_decode(&file[6..10]) as usize])
+    }
+
+    fn chapter(index: u64) -> Chapter {
+        Chapter {
+            title: format!("Turn {}", index),
+            start: Duration::from_secs(index),
+            end: Duration::from_secs(index + 1),
+        }
+    }
+
+    fn tag(chapters: Vec<TocEntry>) -> Tag {
+        Tag {
+            title: String::from("Relays"),
+            album: Some(String::from("Nostr")),
+            artist: Some(String::from("Jaf & Paul ✓")),
+            track: Some(7),
+            date: UNIX_EPOCH + Duration::from_secs(951_782_400),
+            cover: None,
+            chapters,
+        }
+    }
+
+    #[test]
+    fn write_keeps_the_audio_and_replaces_the_tag() {
+        let path = std::env::temp_dir().join(format!("id3-{}-roundtrip.mp3", std::process::id()));
+        let audio: Vec<u8> = (0..=255).cycle().take(4096).collect();
+        std::fs::write(&path, &audio).unwrap();
+
+        write(&path, &tag(vec![TocEntry::Chapter(chapter(0))])).unwrap();
+        let first = std::fs::read(&path).unwrap();
+        assert!(first.starts_with(b"ID3\x03\x00"));
+        assert_eq!(audio_data(&first), audio);
+
+        // A second write replaces the tag instead of stacking another one in front
+        write(&path, &tag(Vec::new())).unwrap();
+        let second = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(audio_data(&second), audio);
+        let ids: Vec<String> = tag_frames(&second).into_iter().map(|(id, _)| id).collect();
+        assert_eq!(ids, ["TIT2", "TALB", "TPE1", "TRCK", "TYER", "TDAT"]);
+
+        let frames = tag_frames(&second);
+        assert_eq!(frames[0].1, b"\x00Relays");
+        assert_eq!(frames[2].1[..3], [1, 0xFF, 0xFE]);
+        assert_eq!(frames[4].1, b"\x002000");
+        assert_eq!(frames[5].1, b"\x002902");
+    }
+
+    #[test]
+    fn audio_data_without_a_tag_is_unchanged() {
+        assert_eq!(audio_data(b"\xFF\xF3\x84\xC0"), b"\xFF\xF3\x84\xC0");
+        assert_eq!(audio_data(b""), b"");
+    }
+
+    #[test]
+    fn civil_date_of_known_days() {
+        let date = |secs: u64| civil_date(UNIX_EPOCH + Duration::from_secs(secs));
+        assert_eq!(date(0), (1970, 1, 1));
+        assert_eq!(date(946_684_799), (1999, 12, 31));
+        assert_eq!(date(951_782_400), (2000, 2, 29));
+        assert_eq!(date(1_709_251_199), (2024, 2, 29));
+        assert_eq!(date(4_107_542_400), (2100, 3, 1));
+    }
+
+    #[test]
+    fn syncsafe_round_trip() {
+        for value in [0, 127, 128, 0x0FFF_FFFF] {
+            let bytes = syncsafe_encode(value);
+            assert!(bytes.iter().all(|byte| byte & 0x80 == 0));
+            assert_eq!(syncsafe_decode(&bytes), value);
+        }
+    }
+
+    #[test]
+    fn groups_get_their_own_table_of_contents() {
+        let entries = vec![
+            TocEntry::Chapter(chapter(0)),
+            TocEntry::Group {
+                title: String::from("Events"),
+                chapters: vec![chapter(1), chapter(2)],
+            },
+        ];
+        let frames = frames(&chapter_frames(&entries));
+        let ids: Vec<&str> = frames.iter().map(|(id, _)| id.as_str()).collect();
+        assert_eq!(ids, ["CHAP", "CHAP", "CHAP", "CTOC", "CTOC"]);
+
+        let group = &frames[3].1;
+        assert!(group.starts_with(b"toc1\x00\x01\x02chp1\x00chp2\x00"));
+        let root = &frames[4].1;
+        assert!(root.starts_with(b"toc\x00\x03\x02chp0\x00toc1\x00"));
+    }
+
+    #[test]
+    fn more_than_255_chapters_are_split_into_parts() {
+        let entries: Vec<TocEntry> = (0..600)
+            .map(|index| TocEntry::Chapter(chapter(index)))
+            .collect();
+        let frames = frames(&chapter_frames(&entries));
+
+        let tocs: Vec<&Vec<u8>> = frames
+            .iter()
+            .filter(|(id, _)| id == "CTOC")
+            .map(|(_, body)| body)
+            .collect();
+        assert_eq!(tocs.len(), 4);
+        assert!(tocs[0].starts_with(b"toc.0\x00\x01\xFFchp0\x00"));
+        assert!(tocs[1].starts_with(b"toc.1\x00\x01\xFFchp255\x00"));
+        assert!(tocs[2].starts_with(b"toc.2\x00\x01\x5Achp510\x00"));
+        assert!(tocs[3].starts_with(b"toc\x00\x03\x03toc.0\x00toc.1\x00toc.2\x00"));
+    }
+}
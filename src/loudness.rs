@@ -0,0 +1,408 @@
+use anyhow::Result;
+use std::f64::consts::PI;
+use std::path::Path;
+
+use crate::audio_merger::{self, Pcm};
+use crate::config::{AudioFormat, LoudnessConfig};
+use crate::manifest::LoudnessRecord;
+use crate::{mp3, probe};
+
+// ITU-R BS.1770 gating: 400 ms blocks every 100 ms, -70 LUFS absolute, 10 LU below relative
+const BLOCK_SECS: f64 = 0.4;
+const BLOCK_STEP_SECS: f64 = 0.1;
+const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+const RELATIVE_GATE_LU: f64 = -10.0;
+
+// True peak is read from a 4x oversampled signal, TAPS samples either side per interpolated point
+const OVERSAMPLING: usize = 4;
+const TAPS: usize = 8;
+
+pub struct Loudness {
+    pub integrated_lufs: f64,
+    pub true_peak_dbtp: f64,
+}
+
+// None when nothing is louder than the absolute gate, silence has no integrated loudness
+pub fn measure(pcm: &Pcm) -> Option<Loudness> {
+    let mut meter = Meter::new(pcm.sample_rate, pcm.channels);
+    meter.add(&pcm.samples);
+    meter.finish()
+}
+
+// Measures a file as it is decoded, without holding its samples
+fn measure_file(path: &Path) -> Result<Option<Loudness>> {
+    let reader = probe::open(path)?;
+    let (sample_rate, channels) = audio_merger::stream_format(reader.as_ref())?;
+    let mut meter = Meter::new(sample_rate, channels);
+    audio_merger::decode_with(reader, |samples| meter.add(samples))?;
+    Ok(meter.finish())
+}
+
+// Takes interleaved samples in any number of pieces. Only the K-weighted power of each 100 ms
+// step is kept, a gating block is four consecutive steps.
+struct Meter {
+    channels: usize,
+    filters: Vec<KWeighting>,
+    step_frames: usize,
+    step_power: f64,
+    step_filled: usize,
+    steps: Vec<f64>,
+    peak: TruePeak,
+}
+
+impl Meter {
+    fn new(sample_rate: u32, channels: usize) -> Self {
+        let channels = channels.max(1);
+        Self {
+            channels,
+            filters: (0..channels)
+                .map(|_| KWeighting::new(sample_rate))
+                .collect(),
+            step_frames: (BLOCK_STEP_SECS * sample_rate as f64) as usize,
+            step_power: 0.0,
+            step_filled: 0,
+            steps: Vec::new(),
+            peak: TruePeak::new(channels),
+        }
+    }
+
+    fn add(&mut self, samples: &[f32]) {
+        for frame in samples.chunks_exact(self.channels) {
+            // Squared K-weighted samples, summed over channels (mono and stereo weigh every
+            // channel 1.0)
+            for (filter, sample) in self.filters.iter_mut().zip(frame) {
+                let weighted = filter.process(*sample as f64);
+                self.step_power += weighted * weighted;
+            }
+            self.peak.add(frame);
+
+            self.step_filled += 1;
+            if self.step_filled == self.step_frames {
+                self.steps.push(self.step_power);
+                self.step_power = 0.0;
+                self.step_filled = 0;
+            }
+        }
+    }
+
+    fn finish(self) -> Option<Loudness> {
+        let per_block = (BLOCK_SECS / BLOCK_STEP_SECS).round() as usize;
+        if self.step_frames == 0 || self.steps.len() < per_block {
+            return None;
+        }
+
+        let block_frames = (per_block * self.step_frames) as f64;
+        let blocks: Vec<f64> = self
+            .steps
+            .windows(per_block)
+            .map(|block| block.iter().sum::<f64>() / block_frames)
+            .filter(|&mean| to_lufs(mean) > ABSOLUTE_GATE_LUFS)
+            .collect();
+        if blocks.is_empty() {
+            return None;
+        }
+
+        let relative_gate = to_lufs(mean(&blocks)) + RELATIVE_GATE_LU;
+        let gated: Vec<f64> = blocks
+            .into_iter()
+            .filter(|&mean| to_lufs(mean) > relative_gate)
+            .collect();
+
+        Some(Loudness {
+            integrated_lufs: to_lufs(mean(&gated)),
+            true_peak_dbtp: 20.0 * self.peak.finish().log10(),
+        })
+    }
+}
+
+fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+fn to_lufs(mean_square: f64) -> f64 {
+    -0.691 + 10.0 * mean_square.log10()
+}
+
+// Highest absolute sample value of the signal interpolated between the samples. Each point
+// between two samples needs TAPS samples either side, so the last 2 * TAPS are kept per channel
+// and interpolation runs TAPS samples behind the input.
+struct TruePeak {
+    // Hann-windowed sinc for each fractional position between two samples
+    phases: Vec<[f64; 2 * TAPS]>,
+    history: Vec<[f64; 2 * TAPS]>,
+    peak: f64,
+}
+
+impl TruePeak {
+    fn new(channels: usize) -> Self {
+        let phases = (1..OVERSAMPLING)
+            .map(|phase| {
+                let offset = phase as f64 / OVERSAMPLING as f64;
+                std::array::from_fn(|tap| {
+                    let t = tap as f64 - (TAPS - 1) as f64 - offset;
+                    let window = 0.5 + 0.5 * (PI * t / TAPS as f64).cos();
+                    let sinc = if t == 0.0 {
+                        1.0
+                    } else {
+                        (PI * t).sin() / (PI * t)
+                    };
+                    sinc * window
+                })
+            })
+            .collect();
+
+        Self {
+            phases,
+            // Silence before the start
+            history: vec![[0.0; 2 * TAPS]; channels],
+            peak: 0.0,
+        }
+    }
+
+    fn add(&mut self, frame: &[f32]) {
+        for (history, sample) in self.history.iter_mut().zip(frame) {
+            let sample = *sample as f64;
+            self.peak = self.peak.max(sample.abs());
+            history.copy_within(1.., 0);
+            history[2 * TAPS - 1] = sample;
+
+            // Between history[TAPS - 1] and history[TAPS]
+            for coefficients in &self.phases {
+                let value: f64 = coefficients
+                    .iter()
+                    .zip(history.iter())
+                    .map(|(coefficient, sample)| coefficient * sample)
+                    .sum();
+                self.peak = self.peak.max(value.abs());
+            }
+        }
+    }
+
+    // Runs silence through so the points between the last samples are covered too
+    fn finish(mut self) -> f64 {
+        let silence = vec![0.0; self.history.len()];
+        for _ in 0..TAPS {
+            self.add(&silence);
+        }
+        self.peak
+    }
+}
+
+// The BS.1770 pre-filter (high shelf) and RLB high-pass, derived for any sample rate
+struct KWeighting {
+    stages: [Biquad; 2],
+}
+
+impl KWeighting {
+    fn new(sample_rate: u32) -> Self {
+        let rate = sample_rate as f64;
+
+        let k = (PI * 1681.974450955533 / rate).tan();
+        let q = 0.7071752369554196;
+        let vh = 10f64.powf(3.999843853973347 / 20.0);
+        let vb = vh.powf(0.4996667741545416);
+        let a0 = 1.0 + k / q + k * k;
+        let shelf = Biquad::new(
+            [
+                (vh + vb * k / q + k * k) / a0,
+                2.0 * (k * k - vh) / a0,
+                (vh - vb * k / q + k * k) / a0,
+            ],
+            [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        );
+
+        let k = (PI * 38.13547087602444 / rate).tan();
+        let q = 0.5003270373238773;
+        let a0 = 1.0 + k / q + k * k;
+        let high_pass = Biquad::new(
+            [1.0, -2.0, 1.0],
+            [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        );
+
+        Self {
+            stages: [shelf, high_pass],
+        }
+    }
+
+    fn process(&mut self, sample: f64) -> f64 {
+        self.stages
+            .iter_mut()
+            .fold(sample, |value, stage| stage.process(value))
+    }
+}
+
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    state: [f64; 2],
+}
+
+impl Biquad {
+    fn new(b: [f64; 3], a: [f64; 2]) -> Self {
+        Self {
+            b,
+            a,
+            state: [0.0; 2],
+        }
+    }
+
+    // Transposed direct form II
+    fn process(&mut self, input: f64) -> f64 {
+        let output = self.b[0] * input + self.state[0];
+        self.state[0] = self.b[1] * input - self.a[0] * output + self.state[1];
+        self.state[1] = self.b[2] * input - self.a[1] * output;
+        output
+    }
+}
+
+// Measures the episode and rewrites it at the target loudness, the gain is capped so the true
+// peak stays under the ceiling. mp3 frames are scaled through their global gain in 1.5 dB steps
+// rather than re-encoded. None when the episode is too quiet to measure.
+pub fn normalize(
+    path: &Path,
+    format: AudioFormat,
+    config: &LoudnessConfig,
+) -> Result<Option<LoudnessRecord>> {
+    // mp3 frames are edited in place, only wav needs the samples kept to scale them
+    let (measured, pcm) = match format {
+        AudioFormat::Mp3 => (measure_file(path)?, None),
+        AudioFormat::Wav => {
+            let pcm = audio_merger::decode(probe::open(path)?)?;
+            (measure(&pcm), Some(pcm))
+        }
+    };
+    let Some(measured) = measured else {
+        return Ok(None);
+    };
+
+    let wanted = (config.target_lufs - measured.integrated_lufs)
+        .min(config.true_peak_dbtp - measured.true_peak_dbtp);
+    let gain_db = match pcm {
+        None => {
+            // Nearest step, one lower when that would push the peak over the ceiling
+            let mut steps = (wanted / mp3::GAIN_STEP_DB).round() as i32;
+            if steps as f64 * mp3::GAIN_STEP_DB > config.true_peak_dbtp - measured.true_peak_dbtp {
+                steps -= 1;
+            }
+            if steps != 0 {
+                let mut reader = probe::open(path)?;
+                let mut episode = Vec::new();
+                while let Some(packet) = audio_merger::next_packet(reader.as_mut())? {
+                    let mut frame = packet.data.to_vec();
+                    mp3::adjust_gain(&mut frame, steps);
+                    episode.extend_from_slice(&frame);
+                }
+                std::fs::write(path, episode)?;
+            }
+            steps as f64 * mp3::GAIN_STEP_DB
+        }
+        Some(mut pcm) => {
+            let factor = 10f32.powf(wanted as f32 / 20.0);
+            for sample in &mut pcm.samples {
+                *sample *= factor;
+            }
+            std::fs::write(path, pcm.to_wav())?;
+            wanted
+        }
+    };
+
+    // Scaling moves loudness and peak by exactly the gain, no need to measure again
+    let round = |value: f64| (value * 100.0).round() / 100.0;
+    Ok(Some(LoudnessRecord {
+        measured_lufs: round(measured.integrated_lufs),
+        measured_true_peak_dbtp: round(measured.true_peak_dbtp),
+        gain_db: round(gain_db),
+        output_lufs: round(measured.integrated_lufs + gain_db),
+        output_true_peak_dbtp: round(measured.true_peak_dbtp + gain_db),
+        target_lufs: config.target_lufs,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(sample_rate: u32, channels: usize, frequency: f64, dbfs: f64, secs: f64) -> Pcm {
+        let amplitude = 10f64.powf(dbfs / 20.0);
+        let frames = (secs * sample_rate as f64) as usize;
+        let samples = (0..frames)
+            .flat_map(|frame| {
+                let value =
+                    amplitude * (2.0 * PI * frequency * frame as f64 / sample_rate as f64).sin();
+                std::iter::repeat_n(value as f32, channels)
+            })
+            .collect();
+        Pcm {
+            sample_rate,
+            channels,
+            samples,
+        }
+    }
+
+    #[test]
+    fn sine_at_minus_20_dbfs_is_minus_23_lufs() {
+        // BS.1770: a 997 Hz sine at -20 dBFS in one channel reads -23.0 LUFS
+        for sample_rate in [44_100, 48_000] {
+            let loudness = measure(&sine(sample_rate, 1, 997.0, -20.0, 5.0)).unwrap();
+            assert!(
+                (loudness.integrated_lufs + 23.0).abs() < 0.05,
+                "{} Hz: {}",
+                sample_rate,
+                loudness.integrated_lufs
+            );
+            assert!((loudness.true_peak_dbtp + 20.0).abs() < 0.1);
+        }
+    }
+
+    #[test]
+    fn stereo_sine_at_minus_23_dbfs_is_minus_23_lufs() {
+        // EBU Tech 3341 case 1: 1 kHz at -23 dBFS in both channels
+        let loudness = measure(&sine(48_000, 2, 1000.0, -23.0, 5.0)).unwrap();
+        assert!(
+            (loudness.integrated_lufs + 23.0).abs() < 0.1,
+            "{}",
+            loudness.integrated_lufs
+        );
+    }
+
+    #[test]
+    fn pieces_measure_the_same_as_a_whole() {
+        let pcm = sine(48_000, 2, 440.0, -12.0, 3.0);
+        let whole = measure(&pcm).unwrap();
+
+        let mut meter = Meter::new(pcm.sample_rate, pcm.channels);
+        for piece in pcm.samples.chunks(1152 * 2) {
+            meter.add(piece);
+        }
+        let pieces = meter.finish().unwrap();
+        assert_eq!(whole.integrated_lufs, pieces.integrated_lufs);
+        assert_eq!(whole.true_peak_dbtp, pieces.true_peak_dbtp);
+    }
+
+    #[test]
+    fn silence_and_short_clips_have_no_loudness() {
+        assert!(measure(&sine(48_000, 1, 997.0, -90.0, 2.0)).is_none());
+        assert!(measure(&sine(48_000, 1, 997.0, -20.0, 0.3)).is_none());
+    }
+
+    #[test]
+    fn true_peak_sees_between_samples() {
+        // A quarter of the sample rate, sampled at 45 degrees, peaks 3 dB above its samples
+        let samples = (0..48_000)
+            .map(|index| (0.5 * (PI / 2.0 * index as f64 + PI / 4.0).sin()) as f32)
+            .collect();
+        let pcm = Pcm {
+            sample_rate: 48_000,
+            channels: 1,
+            samples,
+        };
+        let sample_peak = pcm.samples.iter().fold(0f32, |peak, s| peak.max(s.abs()));
+        let loudness = measure(&pcm).unwrap();
+        assert!(20.0 * (sample_peak as f64).log10() < -8.9);
+        assert!(
+            (loudness.true_peak_dbtp + 6.0).abs() < 0.5,
+            "{}",
+            loudness.true_peak_dbtp
+        );
+    }
+}
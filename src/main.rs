@@ -1,17 +1,82 @@
 use anyhow::Result;
 use async_trait::async_trait;
-use clap::Parser;
+use clap::{Parser, Subcommand, ValueEnum};
 use dialoguer::Select;
+use futures::{Future, TryStreamExt};
 use std::path::{Path, PathBuf};
-use std::time::Instant;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant, SystemTime};
+
+use conversation::{Conversation, ConversationPrompt};
+use manifest::{Manifest, Stage};
+use markdown::FrontMatter;
+use report::FailureReport;
+use stream::PartialOutput;
 
 mod audio_merger;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
-    #[arg(short, long, default_value = "ollama")]
-    model_provider: String,
+    #[command(subcommand)]
+    command: Command,
+
+    /// Process a single markdown file instead of every file in the docs directory
+    #[arg(short, long, global = true)]
+    file: Option<PathBuf>,
+
+    /// Model provider used for conversation generation (defaults to the configured model)
+    #[arg(short, long, global = true, value_enum)]
+    provider: Option<Provider>,
+
+    /// Prompt preset for conversations: conversation, interview, debate, lecture or one from PROMPT_DIR
+    #[arg(long, global = true)]
+    preset: Option<String>,
+
+    /// Override the markdown docs directory (DOCS_PATH)
+    #[arg(long, global = true)]
+    docs: Option<PathBuf>,
+
+    /// Override the audio output directory (AUDIO_OUTPUT_PATH)
+    #[arg(long, global = true)]
+    out: Option<PathBuf>,
+
+    /// Keep processing the remaining files when one fails and report the failures at the end
+    #[arg(long, global = true)]
+    keep_going: bool,
+}
+
+#[derive(Subcommand, Clone, Copy)]
+enum Command {
+    /// Convert markdown to text conversations
+    Convert,
+    /// Convert conversations to audio
+    Tts,
+    /// Generate intros (text and audio)
+    Intro,
+    /// Merge intro audio with conversation audio
+    Merge,
+    /// Normalize the merged episodes to the loudness target
+    Normalize,
+    /// Write ID3 tags and chapter markers into the merged mp3 episodes
+    Tag,
+    /// Write a podcast RSS feed for the merged episodes
+    Feed,
+    /// Full process (all steps)
+    All,
+    /// Choose what to do from interactive menus
+    Interactive,
+}
+
+#[derive(ValueEnum, Clone, Copy)]
+enum Provider {
+    Ollama,
+    #[value(name = "openai")]
+    OpenAI,
+    Anthropic,
+    Gemini,
+    /// Any OpenAI-compatible chat server at COMPATIBLE_BASE_URL
+    Compatible,
 }
 
 pub struct MarkdownProcessor {
@@ -21,13 +86,25 @@ pub struct MarkdownProcessor {
 
 struct ConversationGenerator {
     model_type: config::ModelType,
-    api_key: String,
+    // Chat endpoint for the openai and compatible providers
+    openai: openai::OpenAIEndpoint,
+    anthropic: config::AnthropicConfig,
+    gemini: config::GeminiConfig,
     ollama_url: String,
+    ollama_options: config::OllamaConfig,
+    chunk_size: usize,
+    // Only one stream at a time can own the live progress line
+    live_progress: bool,
+    retry: config::RetryConfig,
+    preset: prompts::PromptPreset,
+    prompt: config::PromptConfig,
 }
 
 struct AudioGenerator {
-    openai_api_key: String,
-    output_path: PathBuf,
+    backend: audio::TtsBackend,
+    voices: config::VoiceConfig,
+    retry: config::RetryConfig,
+    max_chars_per_sec: f64,
 }
 
 // Main processing traits
@@ -37,27 +114,131 @@ pub trait MarkdownProcessing {
 
 #[async_trait]
 trait ConversationGeneration {
-    async fn generate_conversation(&self, content: &str) -> Result<String>;
+    async fn generate_conversation(
+        &self,
+        prompt: &ConversationPrompt,
+        title: &str,
+        content: &str,
+        partial: &mut PartialOutput,
+    ) -> Result<Conversation>;
 }
 
 #[async_trait]
 trait AudioGeneration {
-    async fn generate_audio(&self, conversation: &str, output_file: &Path) -> Result<()>;
+    async fn generate_audio(
+        &self,
+        conversation: &Conversation,
+        voices: &config::VoiceConfig,
+        output_file: &Path,
+    ) -> Result<audio::Speech>;
 }
 
 // Add this function after the existing imports
-fn generate_intro(file_path: &Path) -> Result<(PathBuf, String)> {
+fn generate_intro(file_path: &Path, front_matter: &FrontMatter) -> Result<(PathBuf, String)> {
     let chapter_number = file_path
         .file_stem()
         .and_then(|s| s.to_str())
         .ok_or_else(|| anyhow::anyhow!("Invalid file name"))?;
 
     let intro_filename = file_path.with_file_name(format!("intro_{}.txt", chapter_number));
-    let intro_content = format!("Chapter {}. About NIP-{}.", chapter_number, chapter_number);
+    let chapter = front_matter.chapter.as_deref().unwrap_or(chapter_number);
+    let intro_content = match &front_matter.title {
+        Some(title) => format!("Chapter {}. {}.", chapter, title.trim_end_matches('.')),
+        None => format!("Chapter {}. About NIP-{}.", chapter, chapter_number),
+    };
 
     Ok((intro_filename, intro_content))
 }
 
+fn document_name(file_path: &Path) -> String {
+    file_path
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or_default()
+        .to_string()
+}
+
+// Front matter title, else the first heading, else the file name
+fn document_title(file_path: &Path, front_matter: &FrontMatter, content: &str) -> String {
+    front_matter
+        .title
+        .clone()
+        .or_else(|| markdown::find_title(content))
+        .unwrap_or_else(|| document_name(file_path))
+}
+
+// Episode number for the feed and tags, from the front matter chapter or a numeric file name
+fn episode_number(front_matter: &FrontMatter, document: &str) -> Option<u32> {
+    front_matter
+        .chapter
+        .as_deref()
+        .unwrap_or(document)
+        .parse()
+        .ok()
+}
+
+// Prefer the structured sidecar, fall back to parsing conversations written as plain text
+fn load_conversation(file_path: &Path, speakers: &[String]) -> Result<Conversation> {
+    let json_filename = file_path.with_extension("conversation.json");
+    if json_filename.exists() {
+        return Conversation::load(&json_filename);
+    }
+
+    let text = std::fs::read_to_string(file_path.with_extension("conversation.txt"))?;
+    Ok(Conversation::parse(
+        &document_name(file_path),
+        &text,
+        speakers,
+    ))
+}
+
+// Mark the stage as failed in the manifest before handing the error back
+fn record_failure<T>(
+    manifest: &Manifest,
+    document: &str,
+    stage: Stage,
+    result: Result<T>,
+) -> Result<T> {
+    if let Err(e) = &result {
+        manifest.fail(document, stage, e)?;
+    }
+    result
+}
+
+// Run a stage over all files with at most `concurrency` in flight, stopping at the first error
+// unless the report keeps going. Output names derive from each file's name, so the completion
+// order doesn't matter. Documents whose front matter asks to be skipped are left out.
+async fn for_each_file<'a, F, Fut>(
+    files: &'a [PathBuf],
+    concurrency: usize,
+    stage: Stage,
+    report: &FailureReport,
+    mut f: F,
+) -> Result<()>
+where
+    F: FnMut(&'a PathBuf) -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    futures::stream::iter(files.iter().map(Ok))
+        .try_for_each_concurrent(concurrency.max(1), |file| {
+            // Read per file so broken front matter fails only its own document
+            let work = markdown::read_front_matter(file)
+                .map(|front_matter| (!front_matter.skip).then(|| f(file)));
+            async move {
+                let result = match work {
+                    Ok(Some(work)) => work.await,
+                    Ok(None) => {
+                        println!("Skipping {} (skip in front matter)", file.display());
+                        Ok(())
+                    }
+                    Err(e) => Err(e),
+                };
+                report.handle(stage, file, result)
+            }
+        })
+        .await
+}
+
 // Add this function to format elapsed time nicely
 fn format_elapsed(elapsed: std::time::Duration) -> String {
     let seconds = elapsed.as_secs();
@@ -78,19 +259,259 @@ fn format_elapsed(elapsed: std::time::Duration) -> String {
 #[tokio::main]
 async fn main() -> Result<()> {
     let main_start = Instant::now();
+    let cli = Cli::parse();
 
     // Load configuration
-    let config = config::Config::new()?;
+    let mut config = config::Config::new()?;
+    if let Some(docs) = cli.docs {
+        config.input.docs_path = docs;
+    }
+    if let Some(out) = cli.out {
+        config.output.audio_path = out;
+    }
+    if let Some(preset) = cli.preset {
+        config.prompt.preset = preset;
+    }
 
     // Create output directory if it doesn't exist
     std::fs::create_dir_all(&config.output.audio_path)?;
 
+    // Find all markdown files
+    let markdown_files = markdown::find_markdown_files(&config.input)?;
+    println!("Found {} markdown files to process", markdown_files.len());
+
+    let (command, files_to_process, provider) = match cli.command {
+        Command::Interactive => interactive_menu(&markdown_files)?,
+        command => {
+            let files = match cli.file {
+                Some(file) => vec![resolve_file(&file, &config.input.docs_path)?],
+                None => markdown_files.clone(),
+            };
+            (command, files, cli.provider)
+        }
+    };
+
+    let model_type = resolve_model_type(provider, &config.model)?;
+
+    let markdown_processor = MarkdownProcessor {
+        input_path: config.input.docs_path.clone(),
+        output_path: config.output.audio_path.clone(),
+    };
+
+    // Built only when conversations are generated, other steps don't need a working LLM setup
+    let conversation_generator = || ConversationGenerator::new(&config, model_type.clone());
+
+    // Only built for the stages that synthesize speech, merging needs no TTS backend
+    let audio_generator = || {
+        AudioGenerator::new(
+            &config.tts,
+            openai::OpenAIEndpoint::from_config(&config.model),
+            config.voices.clone(),
+            config.retry.clone(),
+        )
+    };
+
+    let output_path = &config.output.audio_path;
+    let audio_format = config.tts.output_format();
+    let manifest = Manifest::load(output_path)?;
+    let limits = &config.concurrency;
+    let report = FailureReport::new(cli.keep_going);
+
+    // The report is settled however the stages end, so a failures.json left by an earlier run
+    // never outlives this one
+    let result = async {
+        match command {
+            Command::Convert => {
+                let conversation_generator = conversation_generator()?;
+                generate_conversations(
+                    &files_to_process,
+                    &markdown_processor,
+                    &conversation_generator,
+                    &manifest,
+                    limits.llm(&conversation_generator.model_type),
+                    &report,
+                )
+                .await?
+            }
+            Command::Tts => {
+                generate_audio_from_conversations(
+                    &files_to_process,
+                    output_path,
+                    &audio_generator()?,
+                    &config.prompt,
+                    &manifest,
+                    limits.tts,
+                    &report,
+                )
+                .await?
+            }
+            Command::Intro => {
+                generate_intros(
+                    &files_to_process,
+                    output_path,
+                    &audio_generator()?,
+                    &manifest,
+                    limits.tts,
+                    &report,
+                )
+                .await?
+            }
+            Command::Merge => {
+                merge_audio_files(
+                    &files_to_process,
+                    output_path,
+                    audio_format,
+                    &config.merge,
+                    &manifest,
+                    limits.merge,
+                    &report,
+                )
+                .await?
+            }
+            Command::Normalize => {
+                normalize_episodes(
+                    &files_to_process,
+                    output_path,
+                    audio_format,
+                    &config.loudness,
+                    &manifest,
+                    limits.merge,
+                    &report,
+                )
+                .await?
+            }
+            Command::Tag => {
+                tag_episodes(
+                    &files_to_process,
+                    &markdown_processor,
+                    audio_format,
+                    &config,
+                    &manifest,
+                    limits.merge,
+                    &report,
+                )
+                .await?
+            }
+            Command::Feed => generate_feed(
+                &markdown_files,
+                &markdown_processor,
+                output_path,
+                audio_format,
+                &config.feed,
+                &manifest,
+            )?,
+            Command::All => {
+                process_all(
+                    &files_to_process,
+                    &markdown_processor,
+                    &conversation_generator()?,
+                    &audio_generator()?,
+                    &config,
+                    &manifest,
+                    &report,
+                )
+                .await?;
+                // The feed is optional in a full run, it needs to know where the episodes are served
+                if config.feed.base_url.is_some() {
+                    generate_feed(
+                        &markdown_files,
+                        &markdown_processor,
+                        output_path,
+                        audio_format,
+                        &config.feed,
+                        &manifest,
+                    )?;
+                } else {
+                    println!("Skipping podcast feed, FEED_BASE_URL is not set");
+                }
+            }
+            Command::Interactive => unreachable!(),
+        }
+        Ok::<_, anyhow::Error>(())
+    }
+    .await;
+
+    report.finish(output_path)?;
+    result?;
+    let failures = report.failures().len();
+    if failures > 0 {
+        return Err(anyhow::anyhow!("{} file(s) failed", failures));
+    }
+
+    println!(
+        "Processing complete! Total time: {}",
+        format_elapsed(main_start.elapsed())
+    );
+    Ok(())
+}
+
+// Resolve a --file argument either as given or relative to the docs directory
+fn resolve_file(file: &Path, docs_path: &Path) -> Result<PathBuf> {
+    if file.exists() {
+        return Ok(file.to_path_buf());
+    }
+
+    let in_docs = docs_path.join(file);
+    if in_docs.exists() {
+        return Ok(in_docs);
+    }
+
+    Err(anyhow::anyhow!("File not found: {}", file.display()))
+}
+
+// Pick the model for conversation generation, falling back to the configured one
+fn resolve_model_type(
+    provider: Option<Provider>,
+    model_config: &config::ModelConfig,
+) -> Result<config::ModelType> {
+    let (variable, model_type): (&str, fn(String) -> config::ModelType) = match provider {
+        None => return Ok(model_config.model_type.clone()),
+        Some(Provider::Ollama) => ("OLLAMA_MODEL", config::ModelType::Ollama),
+        Some(Provider::OpenAI) => ("OPENAI_MODEL", config::ModelType::OpenAI),
+        Some(Provider::Anthropic) => ("ANTHROPIC_MODEL", config::ModelType::Anthropic),
+        Some(Provider::Gemini) => ("GEMINI_MODEL", config::ModelType::Gemini),
+        Some(Provider::Compatible) => ("COMPATIBLE_MODEL", config::ModelType::Compatible),
+    };
+
+    // The configured model when it is for this provider, e.g. from config.toml
+    let configured = &model_config.model_type;
+    if std::mem::discriminant(configured) == std::mem::discriminant(&model_type(String::new())) {
+        return Ok(configured.clone());
+    }
+
+    std::env::var(variable).map(model_type).map_err(|_| {
+        anyhow::anyhow!(
+            "{} must be set to use the {} provider",
+            variable,
+            variable.trim_end_matches("_MODEL").to_lowercase()
+        )
+    })
+}
+
+// Drive the original dialoguer menus and translate the answers into a command
+fn interactive_menu(
+    markdown_files: &[PathBuf],
+) -> Result<(Command, Vec<PathBuf>, Option<Provider>)> {
+    let commands = [
+        Command::Convert,
+        Command::Tts,
+        Command::Intro,
+        Command::Merge,
+        Command::Normalize,
+        Command::Tag,
+        Command::Feed,
+        Command::All,
+    ];
+
     // Main menu options
     let options = vec![
         "Convert markdown to text conversations",
         "Convert conversations to audio",
         "Generate intros (text and audio)",
         "Merge intro audio with conversation audio",
+        "Normalize episode loudness",
+        "Write ID3 tags and chapters",
+        "Write podcast feed",
         "Full process (all steps)",
         "Process specific file",
     ];
@@ -98,15 +519,10 @@ async fn main() -> Result<()> {
     let selection = Select::new()
         .with_prompt("Choose processing mode")
         .items(&options)
-        .default(4) // Default to full process
+        .default(7) // Default to full process
         .interact()?;
 
-    // Find all markdown files
-    let markdown_files = markdown::find_markdown_files(&config.input.docs_path)?;
-    println!("Found {} markdown files to process", markdown_files.len());
-
-    // For specific file processing
-    let files_to_process = if selection == 5 {
+    let (command, files) = if selection == 8 {
         // Create a list of file names for selection
         let file_names: Vec<String> = markdown_files
             .iter()
@@ -124,168 +540,68 @@ async fn main() -> Result<()> {
             .default(0)
             .interact()?;
 
-        // Find the selected file path
-        let selected_file = &file_names[file_selection];
-        let selected_path = markdown_files
-            .iter()
-            .find(|path| {
-                path.file_name()
-                    .and_then(|name| name.to_str())
-                    .map_or(false, |name| name == selected_file)
-            })
-            .ok_or_else(|| anyhow::anyhow!("Selected file not found"))?;
-
-        // Now let user select which operations to perform
+        // Now let user select which operations to perform, the feed always covers every episode
+        let file_commands = [
+            Command::Convert,
+            Command::Tts,
+            Command::Intro,
+            Command::Merge,
+            Command::Normalize,
+            Command::Tag,
+            Command::All,
+        ];
         let operation_options = vec![
             "Convert to conversation",
             "Generate audio",
             "Generate intro",
             "Merge audio files",
+            "Normalize loudness",
+            "Write ID3 tags",
             "All operations",
         ];
 
         let operation_selection = Select::new()
             .with_prompt("Choose operation for this file")
             .items(&operation_options)
-            .default(4)
+            .default(6)
             .interact()?;
 
-        // Create a vector with just the selected file
-        vec![selected_path.clone()]
+        (
+            file_commands[operation_selection],
+            vec![markdown_files[file_selection].clone()],
+        )
     } else {
-        // Process all files
-        markdown_files
-    };
-
-    // Initialize processors
-    let model_type =
-        if selection == 0 || selection == 4 || (selection == 5 && files_to_process.len() == 1) {
-            // Only ask for model if we need conversation generation
-            let model_options = vec!["Ollama", "OpenAI"];
-            let model_selection = Select::new()
-                .with_prompt("Choose your model provider")
-                .items(&model_options)
-                .default(0)
-                .interact()?;
-
-            match model_selection {
-                0 => config::ModelType::Ollama(
-                    std::env::var("OLLAMA_MODEL").expect("OLLAMA_MODEL must be set"),
-                ),
-                1 => config::ModelType::OpenAI(
-                    std::env::var("OPENAI_MODEL").expect("OPENAI_MODEL must be set"),
-                ),
-                _ => unreachable!(),
-            }
-        } else {
-            // Default model type for other operations
-            config::ModelType::OpenAI("gpt-4o".to_string())
-        };
-
-    let markdown_processor = MarkdownProcessor {
-        input_path: config.input.docs_path.clone(),
-        output_path: config.output.audio_path.clone(),
-    };
-
-    let conversation_generator = ConversationGenerator {
-        model_type,
-        api_key: config.model.openai_api_key.clone().unwrap_or_default(),
-        ollama_url: config
-            .model
-            .ollama_base_url
-            .unwrap_or_else(|| String::from("http://localhost:11434")),
+        (commands[selection], markdown_files.to_vec())
     };
 
-    let audio_path = config.output.audio_path.clone();
-    let audio_generator = AudioGenerator {
-        openai_api_key: config
-            .model
-            .openai_api_key
-            .expect("OpenAI API key is required for audio generation"),
-        output_path: audio_path,
-    };
-
-    let output_path = config.output.audio_path;
-
-    if selection == 5 {
-        // Process specific file with selected operation
-        let operation_selection = Select::new()
-            .with_prompt("Choose operation for this file")
-            .items(&[
-                "Convert to conversation",
-                "Generate audio",
-                "Generate intro",
-                "Merge audio files",
-                "All operations",
-            ])
-            .default(4)
+    // Only ask for model if we need conversation generation
+    let provider = if matches!(command, Command::Convert | Command::All) {
+        let providers = [
+            Provider::Ollama,
+            Provider::OpenAI,
+            Provider::Anthropic,
+            Provider::Gemini,
+            Provider::Compatible,
+        ];
+        let model_options = vec![
+            "Ollama",
+            "OpenAI",
+            "Anthropic",
+            "Gemini",
+            "OpenAI-compatible server",
+        ];
+        let model_selection = Select::new()
+            .with_prompt("Choose your model provider")
+            .items(&model_options)
+            .default(0)
             .interact()?;
 
-        match operation_selection {
-            0 => {
-                generate_conversations(
-                    &files_to_process,
-                    &markdown_processor,
-                    &conversation_generator,
-                )
-                .await?
-            }
-            1 => {
-                generate_audio_from_conversations(&files_to_process, &output_path, &audio_generator)
-                    .await?
-            }
-            2 => generate_intros(&files_to_process, &output_path, &audio_generator).await?,
-            3 => merge_audio_files(&files_to_process, &config.input.docs_path, &output_path)?,
-            4 => {
-                process_all(
-                    &files_to_process,
-                    &markdown_processor,
-                    &conversation_generator,
-                    &audio_generator,
-                    &config.input.docs_path,
-                    &output_path,
-                )
-                .await?
-            }
-            _ => unreachable!(),
-        }
+        Some(providers[model_selection])
     } else {
-        // Process all files with selected operation
-        match selection {
-            0 => {
-                generate_conversations(
-                    &files_to_process,
-                    &markdown_processor,
-                    &conversation_generator,
-                )
-                .await?
-            }
-            1 => {
-                generate_audio_from_conversations(&files_to_process, &output_path, &audio_generator)
-                    .await?
-            }
-            2 => generate_intros(&files_to_process, &output_path, &audio_generator).await?,
-            3 => merge_audio_files(&files_to_process, &config.input.docs_path, &output_path)?,
-            4 => {
-                process_all(
-                    &files_to_process,
-                    &markdown_processor,
-                    &conversation_generator,
-                    &audio_generator,
-                    &config.input.docs_path,
-                    &output_path,
-                )
-                .await?
-            }
-            _ => unreachable!(),
-        }
-    }
+        None
+    };
 
-    println!(
-        "Processing complete! Total time: {}",
-        format_elapsed(main_start.elapsed())
-    );
-    Ok(())
+    Ok((command, files, provider))
 }
 
 // Function to generate conversations from markdown
@@ -293,55 +609,96 @@ async fn generate_conversations(
     files: &[PathBuf],
     markdown_processor: &MarkdownProcessor,
     conversation_generator: &ConversationGenerator,
+    manifest: &Manifest,
+    concurrency: usize,
+    report: &FailureReport,
 ) -> Result<()> {
     println!("Converting markdown to conversations...");
     let start_time = Instant::now();
-    let mut processed = 0;
-
-    for file in files {
-        let file_start = Instant::now();
-        let conv_filename = file.with_extension("conversation.txt");
-
-        if conv_filename.exists() {
-            println!(
-                "Skipping existing conversation: {}",
-                conv_filename.display()
-            );
-            continue;
-        }
-
-        println!("Processing: {}", file.display());
-        let content = markdown_processor.process_markdown(file)?;
+    let processed = AtomicUsize::new(0);
+    let processed = &processed;
+
+    for_each_file(
+        files,
+        concurrency,
+        Stage::Conversation,
+        report,
+        |file| async move {
+            let file_start = Instant::now();
+            let conv_filename = file.with_extension("conversation.txt");
+            let conv_json_filename = file.with_extension("conversation.json");
+            let partial_filename = file.with_extension("conversation.partial.txt");
+            let document = document_name(file);
+
+            let source = std::fs::read(file)?;
+            let source_hash = manifest::hash(&[&source]);
+            let model = conversation_generator.model_type.to_string();
+
+            let front_matter = markdown::read_front_matter(file)?;
+            let content = markdown_processor.process_markdown(file)?;
+            let title = document_title(file, &front_matter, &content);
+            let prompt = conversation_generator.prompt_for(&title, &front_matter)?;
+            // Chunking decides what each part of the conversation covers
+            let chunk_size = conversation_generator.chunk_size.to_string();
+            let input_hash = manifest::hash(&[
+                source_hash.as_bytes(),
+                model.as_bytes(),
+                conversation_generator.provider().as_bytes(),
+                prompt.fingerprint()?.as_bytes(),
+                chunk_size.as_bytes(),
+            ]);
+
+            if manifest.is_up_to_date(
+                &document,
+                Stage::Conversation,
+                &input_hash,
+                &conv_filename,
+            )? {
+                println!(
+                    "Skipping up-to-date conversation: {}",
+                    conv_filename.display()
+                );
+                return Ok(());
+            }
 
-        // Limit conversation text length
-        let content = if content.len() > 4000 {
+            println!("Processing: {}", file.display());
+            manifest.start(&document, Stage::Conversation, &input_hash)?;
+            let result = async {
+                // Streamed text lands here first and survives an interrupted run
+                let mut partial = PartialOutput::create(&partial_filename)?;
+                let conversation = conversation_generator
+                    .generate_conversation(&prompt, &title, &content, &mut partial)
+                    .await?;
+                std::fs::write(&conv_filename, conversation.to_string())?;
+                conversation.save(&conv_json_filename)?;
+                partial.finish()?;
+                Ok(conversation)
+            }
+            .await;
+            let conversation = record_failure(manifest, &document, Stage::Conversation, result)?;
+            manifest.finish(&document, Stage::Conversation, |entry| {
+                entry.source_hash = Some(source_hash);
+                entry.conversation_hash =
+                    Some(manifest::hash(&[conversation.to_string().as_bytes()]));
+                entry.model = Some(model);
+            })?;
+
+            let file_elapsed = file_start.elapsed();
+            processed.fetch_add(1, Ordering::Relaxed);
             println!(
-                "Warning: Truncating content to 4000 characters for {}",
-                file.display()
+                "Created conversation: {} (took {})",
+                conv_filename.display(),
+                format_elapsed(file_elapsed)
             );
-            content.chars().take(4000).collect::<String>()
-        } else {
-            content
-        };
-
-        let conversation = conversation_generator
-            .generate_conversation(&content)
-            .await?;
-        std::fs::write(&conv_filename, &conversation)?;
-
-        let file_elapsed = file_start.elapsed();
-        processed += 1;
-        println!(
-            "Created conversation: {} (took {})",
-            conv_filename.display(),
-            format_elapsed(file_elapsed)
-        );
-    }
+            Ok(())
+        },
+    )
+    .await?;
 
     let total_elapsed = start_time.elapsed();
     println!(
         "Conversation generation complete! Processed {} files in {}",
-        processed,
+        processed.load(Ordering::Relaxed),
         format_elapsed(total_elapsed)
     );
     Ok(())
@@ -352,44 +709,78 @@ async fn generate_audio_from_conversations(
     files: &[PathBuf],
     output_path: &Path,
     audio_generator: &AudioGenerator,
+    prompt: &config::PromptConfig,
+    manifest: &Manifest,
+    concurrency: usize,
+    report: &FailureReport,
 ) -> Result<()> {
     println!("Converting conversations to audio...");
 
-    for file in files {
-        let chapter_number = file
-            .file_stem()
-            .and_then(|s| s.to_str())
-            .ok_or_else(|| anyhow::anyhow!("Invalid file name"))?;
-
-        // Check for conversation file in the same directory as the markdown file
-        let conv_filename = file.with_extension("conversation.txt");
-
-        // Output audio goes to the output directory
-        let audio_filename = output_path.join(format!("{}.mp3", chapter_number));
-
-        println!("Checking: {}", file.display());
-        println!("  Conversation file: {}", conv_filename.display());
-        println!("  Audio file: {}", audio_filename.display());
-        println!("  Conversation exists: {}", conv_filename.exists());
-        println!("  Audio exists: {}", audio_filename.exists());
-
-        if !conv_filename.exists() {
-            println!("Skipping file without conversation: {}", file.display());
-            continue;
-        }
+    for_each_file(
+        files,
+        concurrency,
+        Stage::Audio,
+        report,
+        |file| async move {
+            let chapter_number = file
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .ok_or_else(|| anyhow::anyhow!("Invalid file name"))?;
+
+            // Check for conversation file in the same directory as the markdown file
+            let conv_filename = file.with_extension("conversation.txt");
+
+            // Output audio goes to the output directory
+            let audio_filename = output_path.join(format!(
+                "{}.{}",
+                chapter_number,
+                audio_generator.format().extension()
+            ));
+
+            println!("Checking: {}", file.display());
+            println!("  Conversation file: {}", conv_filename.display());
+            println!("  Audio file: {}", audio_filename.display());
+            println!("  Conversation exists: {}", conv_filename.exists());
+            println!("  Audio exists: {}", audio_filename.exists());
+
+            if !conv_filename.exists() {
+                println!("Skipping file without conversation: {}", file.display());
+                return Ok(());
+            }
 
-        if audio_filename.exists() {
-            println!("Skipping existing audio: {}", audio_filename.display());
-            continue;
-        }
+            let front_matter = markdown::read_front_matter(file)?;
+            let conversation =
+                load_conversation(file, &conversation::speaker_list(&front_matter, prompt))?;
+            let voices = audio_generator.voices_for(&front_matter);
+            let voice = audio_generator.voice_assignments(&conversation, &voices);
+            let input_hash =
+                manifest::hash(&[conversation.to_string().as_bytes(), voice.as_bytes()]);
+
+            if manifest.is_up_to_date(chapter_number, Stage::Audio, &input_hash, &audio_filename)? {
+                println!("Skipping up-to-date audio: {}", audio_filename.display());
+                return Ok(());
+            }
 
-        println!("Generating audio for: {}", conv_filename.display());
-        let conversation = std::fs::read_to_string(&conv_filename)?;
-        audio_generator
-            .generate_audio(&conversation, &audio_filename)
-            .await?;
-        println!("Created audio: {}", audio_filename.display());
-    }
+            println!("Generating audio for: {}", conv_filename.display());
+            manifest.start(chapter_number, Stage::Audio, &input_hash)?;
+            let result = audio_generator
+                .generate_audio(&conversation, &voices, &audio_filename)
+                .await;
+            let speech = record_failure(manifest, chapter_number, Stage::Audio, result)?;
+            manifest.finish(chapter_number, Stage::Audio, |entry| {
+                entry.voice = Some(voice);
+                entry.turn_durations_ms = speech
+                    .turn_durations
+                    .iter()
+                    .map(|duration| duration.as_millis() as u64)
+                    .collect();
+                entry.record_audio(Stage::Audio, speech.info);
+            })?;
+            println!("Created audio: {}", audio_filename.display());
+            Ok(())
+        },
+    )
+    .await?;
 
     Ok(())
 }
@@ -399,86 +790,547 @@ async fn generate_intros(
     files: &[PathBuf],
     output_path: &Path,
     audio_generator: &AudioGenerator,
+    manifest: &Manifest,
+    concurrency: usize,
+    report: &FailureReport,
 ) -> Result<()> {
     println!("Generating intros...");
 
-    for file in files {
-        let chapter_number = file
-            .file_stem()
-            .and_then(|s| s.to_str())
-            .ok_or_else(|| anyhow::anyhow!("Invalid file name"))?;
+    for_each_file(
+        files,
+        concurrency,
+        Stage::Intro,
+        report,
+        |file| async move {
+            let chapter_number = file
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .ok_or_else(|| anyhow::anyhow!("Invalid file name"))?;
+
+            let front_matter = markdown::read_front_matter(file)?;
+            let (intro_filename, intro_content) = generate_intro(file, &front_matter)?;
+            let intro_audio_filename = output_path.join(format!(
+                "intro_{}.{}",
+                chapter_number,
+                audio_generator.format().extension()
+            ));
+
+            // Narration only, the intro has no speaker labels
+            let intro = Conversation::parse(chapter_number, &intro_content, &[]);
+            let voices = audio_generator.voices_for(&front_matter);
+            let voice = audio_generator.voice_assignments(&intro, &voices);
+            let input_hash = manifest::hash(&[intro_content.as_bytes(), voice.as_bytes()]);
+
+            if manifest.is_up_to_date(
+                chapter_number,
+                Stage::Intro,
+                &input_hash,
+                &intro_audio_filename,
+            )? {
+                println!(
+                    "Skipping up-to-date intro audio: {}",
+                    intro_audio_filename.display()
+                );
+                return Ok(());
+            }
 
-        let (intro_filename, intro_content) = generate_intro(file)?;
-        let intro_audio_filename = output_path.join(format!("intro_{}.mp3", chapter_number));
+            std::fs::write(&intro_filename, &intro_content)?;
+            println!("Created intro text: {}", intro_filename.display());
+
+            manifest.start(chapter_number, Stage::Intro, &input_hash)?;
+            let result = audio_generator
+                .generate_audio(&intro, &voices, &intro_audio_filename)
+                .await;
+            let speech = record_failure(manifest, chapter_number, Stage::Intro, result)?;
+            manifest.finish(chapter_number, Stage::Intro, |entry| {
+                entry.record_audio(Stage::Intro, speech.info)
+            })?;
+            println!("Created intro audio: {}", intro_audio_filename.display());
+            Ok(())
+        },
+    )
+    .await?;
 
-        if intro_audio_filename.exists() {
-            println!(
-                "Skipping existing intro audio: {}",
-                intro_audio_filename.display()
-            );
-            continue;
-        }
+    Ok(())
+}
 
-        std::fs::write(&intro_filename, &intro_content)?;
-        println!("Created intro text: {}", intro_filename.display());
+// Function to merge audio files
+async fn merge_audio_files(
+    files: &[PathBuf],
+    output_path: &Path,
+    format: config::AudioFormat,
+    merge: &config::MergeConfig,
+    manifest: &Manifest,
+    concurrency: usize,
+    report: &FailureReport,
+) -> Result<()> {
+    println!("Merging audio files...");
+    merge.check(format)?;
+
+    for_each_file(
+        files,
+        concurrency,
+        Stage::Merge,
+        report,
+        |file| async move {
+            let chapter_number = file
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .ok_or_else(|| anyhow::anyhow!("Invalid file name"))?;
+
+            let extension = format.extension();
+            let merged_audio =
+                output_path.join(format!("chapter_{}.{}", chapter_number, extension));
+
+            let Some(segments) =
+                episode_segments(&merge.segments, output_path, chapter_number, extension)?
+            else {
+                println!(
+                    "Skipping merge for chapter {}: missing source files",
+                    chapter_number
+                );
+                return Ok(());
+            };
+
+            // The layout is part of the input, changing a gap or fade re-assembles the episode
+            let mut inputs = vec![format!("{:?}", merge).into_bytes()];
+            for segment in &segments {
+                inputs.push(std::fs::read(&segment.path)?);
+            }
+            let input_hash = manifest::hash(&inputs.iter().map(Vec::as_slice).collect::<Vec<_>>());
+            if manifest.is_up_to_date(chapter_number, Stage::Merge, &input_hash, &merged_audio)? {
+                println!(
+                    "Skipping up-to-date merged audio: {}",
+                    merged_audio.display()
+                );
+                return Ok(());
+            }
 
-        audio_generator
-            .generate_audio(&intro_content, &intro_audio_filename)
+            println!("Merging audio for chapter {}", chapter_number);
+            manifest.start(chapter_number, Stage::Merge, &input_hash)?;
+            // Decoding and ffmpeg both block, keep them off the async worker threads
+            let (backend, merged) = (merge.backend, merged_audio.clone());
+            let result = tokio::task::spawn_blocking(move || {
+                audio_merger::merge_audio_files(backend, format, &segments, &merged)
+            })
             .await?;
-        println!("Created intro audio: {}", intro_audio_filename.display());
-    }
+            record_failure(manifest, chapter_number, Stage::Merge, result)?;
+            manifest.finish(chapter_number, Stage::Merge, |entry| {
+                entry.published_at.get_or_insert_with(manifest::now);
+            })?;
+            Ok(())
+        },
+    )
+    .await?;
 
     Ok(())
 }
 
-// Function to merge audio files
-fn merge_audio_files(files: &[PathBuf], input_path: &Path, output_path: &Path) -> Result<()> {
-    println!("Merging audio files...");
+// Function to bring the merged episodes to the loudness target
+async fn normalize_episodes(
+    files: &[PathBuf],
+    output_path: &Path,
+    format: config::AudioFormat,
+    loudness: &config::LoudnessConfig,
+    manifest: &Manifest,
+    concurrency: usize,
+    report: &FailureReport,
+) -> Result<()> {
+    println!("Normalizing episode loudness...");
+
+    for_each_file(
+        files,
+        concurrency,
+        Stage::Loudness,
+        report,
+        |file| async move {
+            let chapter_number = document_name(file);
+            let episode =
+                output_path.join(format!("chapter_{}.{}", chapter_number, format.extension()));
+            if !episode.exists() {
+                println!(
+                    "Skipping loudness for chapter {}: no merged episode",
+                    chapter_number
+                );
+                return Ok(());
+            }
 
-    for file in files {
-        let chapter_number = file
-            .file_stem()
-            .and_then(|s| s.to_str())
-            .ok_or_else(|| anyhow::anyhow!("Invalid file name"))?;
-
-        let intro_audio = input_path.join(format!("intro_{}.mp3", chapter_number));
-        let content_audio = input_path.join(format!("{}.mp3", chapter_number));
-        let merged_audio = output_path.join(format!("chapter_{}.mp3", chapter_number));
-
-        if merged_audio.exists() {
-            println!("Skipping existing merged audio: {}", merged_audio.display());
-            continue;
-        }
+            // The episode is rewritten in place, so the hash of the result is stored once done
+            let settings = format!("{:?}", loudness).into_bytes();
+            let input_hash =
+                manifest::hash(&[&settings, id3::audio_data(&std::fs::read(&episode)?)]);
+            if manifest.is_up_to_date(&chapter_number, Stage::Loudness, &input_hash, &episode)? {
+                println!("Skipping normalized episode: {}", episode.display());
+                return Ok(());
+            }
+
+            println!("Normalizing loudness for chapter {}", chapter_number);
+            manifest.start(&chapter_number, Stage::Loudness, &input_hash)?;
+            let (target, path) = (loudness.clone(), episode.clone());
+            let result =
+                tokio::task::spawn_blocking(move || loudness::normalize(&path, format, &target))
+                    .await?;
+            let measured = record_failure(manifest, &chapter_number, Stage::Loudness, result)?;
+            match &measured {
+                Some(record) => println!(
+                    "Chapter {}: {:.1} LUFS, {:.1} dBTP, applied {:+.1} dB",
+                    chapter_number,
+                    record.measured_lufs,
+                    record.measured_true_peak_dbtp,
+                    record.gain_db
+                ),
+                None => println!(
+                    "Chapter {} is too quiet to measure, left as is",
+                    chapter_number
+                ),
+            }
+            let output_hash =
+                manifest::hash(&[&settings, id3::audio_data(&std::fs::read(&episode)?)]);
+            manifest.finish(&chapter_number, Stage::Loudness, |entry| {
+                entry.loudness = measured;
+                if let Some(record) = entry.stages.get_mut(&Stage::Loudness) {
+                    record.input_hash = output_hash;
+                }
+            })?;
+            Ok(())
+        },
+    )
+    .await?;
 
-        if !intro_audio.exists() || !content_audio.exists() {
+    Ok(())
+}
+
+// Function to write ID3 tags and chapter markers into the merged episodes
+async fn tag_episodes(
+    files: &[PathBuf],
+    markdown_processor: &MarkdownProcessor,
+    format: config::AudioFormat,
+    config: &config::Config,
+    manifest: &Manifest,
+    concurrency: usize,
+    report: &FailureReport,
+) -> Result<()> {
+    if format != config::AudioFormat::Mp3 {
+        println!("Skipping ID3 tags, they are only written to mp3 episodes");
+        return Ok(());
+    }
+    println!("Writing ID3 tags...");
+
+    let output_path = &config.output.audio_path;
+    let album = config.tags.album.clone().or_else(|| {
+        markdown_processor
+            .input_path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .map(String::from)
+    });
+    let cover = config
+        .tags
+        .cover
+        .as_deref()
+        .map(id3::Picture::load)
+        .transpose()?;
+    let (album, cover) = (&album, &cover);
+
+    for_each_file(files, concurrency, Stage::Tags, report, |file| async move {
+        let chapter_number = document_name(file);
+        let episode = output_path.join(format!("chapter_{}.mp3", chapter_number));
+        if !episode.exists() {
             println!(
-                "Skipping merge for chapter {}: missing source files",
+                "Skipping tags for chapter {}: no merged episode",
                 chapter_number
             );
-            continue;
+            return Ok(());
         }
 
-        println!("Merging audio for chapter {}", chapter_number);
-        audio_merger::merge_audio_files(&intro_audio, &content_audio, &merged_audio)?;
+        let front_matter = markdown::read_front_matter(file)?;
+        let content = markdown_processor.process_markdown(file)?;
+        let conversation = load_conversation(
+            file,
+            &conversation::speaker_list(&front_matter, &config.prompt),
+        )?;
+        let turn_durations = manifest
+            .entry(&chapter_number)
+            .map(|entry| entry.turn_durations_ms)
+            .unwrap_or_default();
+        let tag = id3::Tag {
+            title: document_title(file, &front_matter, &content),
+            album: album.clone(),
+            artist: config.tags.artist.clone().or_else(|| {
+                // Several artists are separated by slashes in ID3v2.3
+                (!conversation.participants.is_empty()).then(|| conversation.participants.join("/"))
+            }),
+            track: episode_number(&front_matter, &chapter_number),
+            date: SystemTime::now(),
+            chapters: episode_chapters(
+                &config.merge,
+                output_path,
+                &chapter_number,
+                &conversation,
+                &turn_durations,
+            )?,
+            cover: cover.clone(),
+        };
+
+        // Tags sit in front of the audio, so only the audio itself is hashed
+        let audio = std::fs::read(&episode)?;
+        let metadata = format!(
+            "{:?} {:?} {:?} {:?} {:?}",
+            tag.title, tag.album, tag.artist, tag.track, tag.chapters
+        );
+        let cover_data = cover.as_ref().map_or(&[][..], |cover| &cover.data);
+        let input_hash =
+            manifest::hash(&[metadata.as_bytes(), cover_data, id3::audio_data(&audio)]);
+        if manifest.is_up_to_date(&chapter_number, Stage::Tags, &input_hash, &episode)? {
+            println!("Skipping up-to-date tags: {}", episode.display());
+            return Ok(());
+        }
+
+        manifest.start(&chapter_number, Stage::Tags, &input_hash)?;
+        let result = id3::write(&episode, &tag);
+        record_failure(manifest, &chapter_number, Stage::Tags, result)?;
+        manifest.finish(&chapter_number, Stage::Tags, |_| {})?;
+        println!("Tagged episode: {}", episode.display());
+        Ok(())
+    })
+    .await?;
+
+    Ok(())
+}
+
+// Chapter markers for the intro and the conversation, placed by replaying the episode layout
+fn episode_chapters(
+    merge: &config::MergeConfig,
+    output_path: &Path,
+    chapter_number: &str,
+    conversation: &Conversation,
+    turn_durations_ms: &[u64],
+) -> Result<Vec<id3::TocEntry>> {
+    let Some(segments) = episode_segments(&merge.segments, output_path, chapter_number, "mp3")?
+    else {
+        return Ok(Vec::new());
+    };
+
+    let mut chapters = Vec::new();
+    let mut position = Duration::ZERO;
+    for (segment, layout) in segments.iter().zip(&merge.segments) {
+        // A crossfaded segment starts that much before the previous one ends
+        position = position.saturating_sub(segment.crossfade);
+        let length = probe::duration(&segment.path)?;
+        match layout.source {
+            config::SegmentSource::Intro => chapters.push(id3::TocEntry::Chapter(id3::Chapter {
+                title: String::from("Intro"),
+                start: position,
+                end: position + length,
+            })),
+            config::SegmentSource::Body => chapters.extend(conversation_chapters(
+                conversation,
+                turn_durations_ms,
+                position,
+                length,
+            )),
+            config::SegmentSource::File(_) => {}
+        }
+        position += length + segment.gap;
+    }
+    Ok(chapters)
+}
+
+// A chapter per speaker turn grouped by section. Audio rendered before turn durations were
+// recorded gets one chapter for the whole conversation.
+fn conversation_chapters(
+    conversation: &Conversation,
+    turn_durations_ms: &[u64],
+    start: Duration,
+    length: Duration,
+) -> Vec<id3::TocEntry> {
+    let turns = &conversation.turns;
+    if turns.is_empty() || turn_durations_ms.len() != turns.len() {
+        return vec![id3::TocEntry::Chapter(id3::Chapter {
+            title: String::from("Conversation"),
+            start,
+            end: start + length,
+        })];
     }
 
+    let mut position = start;
+    let mut chapters = turns.iter().zip(turn_durations_ms).map(|(turn, ms)| {
+        let chapter = id3::Chapter {
+            title: turn.preview(48),
+            start: position,
+            end: position + Duration::from_millis(*ms),
+        };
+        position = chapter.end;
+        chapter
+    });
+
+    let sections = &conversation.sections;
+    if sections.is_empty() {
+        return chapters.map(id3::TocEntry::Chapter).collect();
+    }
+    sections
+        .iter()
+        .enumerate()
+        .map(|(index, section)| {
+            let end = sections
+                .get(index + 1)
+                .map_or(turns.len(), |next| next.first_turn);
+            id3::TocEntry::Group {
+                title: section.title.clone(),
+                chapters: chapters
+                    .by_ref()
+                    .take(end.saturating_sub(section.first_turn))
+                    .collect(),
+            }
+        })
+        .filter(
+            |entry| !matches!(entry, id3::TocEntry::Group { chapters, .. } if chapters.is_empty()),
+        )
+        .collect()
+}
+
+// The configured episode layout as files, None when the chapter's own audio is missing
+fn episode_segments(
+    layout: &[config::SegmentConfig],
+    output_path: &Path,
+    chapter_number: &str,
+    extension: &str,
+) -> Result<Option<Vec<audio_merger::Segment>>> {
+    let mut segments = Vec::with_capacity(layout.len());
+    for segment in layout {
+        // Intro and conversation audio are written to the output directory by their stages
+        let path = match &segment.source {
+            config::SegmentSource::Intro => {
+                output_path.join(format!("intro_{}.{}", chapter_number, extension))
+            }
+            config::SegmentSource::Body => {
+                output_path.join(format!("{}.{}", chapter_number, extension))
+            }
+            config::SegmentSource::File(path) => {
+                if !path.exists() {
+                    return Err(anyhow::anyhow!(
+                        "Episode segment {} does not exist",
+                        path.display()
+                    ));
+                }
+                path.clone()
+            }
+        };
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        segments.push(audio_merger::Segment {
+            path,
+            gap: Duration::from_millis(segment.gap_ms),
+            fade_in: Duration::from_millis(segment.fade_in_ms),
+            fade_out: Duration::from_millis(segment.fade_out_ms),
+            crossfade: Duration::from_millis(segment.crossfade_ms),
+        });
+    }
+    Ok(Some(segments))
+}
+
+// Function to write the podcast feed for every merged episode
+fn generate_feed(
+    files: &[PathBuf],
+    markdown_processor: &MarkdownProcessor,
+    output_path: &Path,
+    format: config::AudioFormat,
+    config: &config::FeedConfig,
+    manifest: &Manifest,
+) -> Result<()> {
+    println!("Writing podcast feed...");
+
+    let mut episodes = Vec::new();
+    for file in files {
+        // One broken document shouldn't take the whole feed down
+        match feed_episode(file, markdown_processor, output_path, format, manifest) {
+            Ok(Some(episode)) => episodes.push(episode),
+            Ok(None) => {}
+            Err(e) => println!("Leaving {} out of the feed: {:#}", file.display(), e),
+        }
+    }
+
+    let feed_path = feed::write(config, format, &episodes, output_path)?;
+    println!(
+        "Created podcast feed with {} episodes: {}",
+        episodes.len(),
+        feed_path.display()
+    );
     Ok(())
 }
 
+// None for documents that are skipped or have no merged episode yet
+fn feed_episode(
+    file: &Path,
+    markdown_processor: &MarkdownProcessor,
+    output_path: &Path,
+    format: config::AudioFormat,
+    manifest: &Manifest,
+) -> Result<Option<feed::Episode>> {
+    let front_matter = markdown::read_front_matter(file)?;
+    if front_matter.skip {
+        return Ok(None);
+    }
+
+    let document = document_name(file);
+    let audio = output_path.join(format!("chapter_{}.{}", document, format.extension()));
+    if !audio.exists() {
+        println!(
+            "Leaving {} out of the feed: no merged episode",
+            file.display()
+        );
+        return Ok(None);
+    }
+
+    let content = markdown_processor.process_markdown(file)?;
+    let title = document_title(file, &front_matter, &content);
+    let description = front_matter
+        .description
+        .clone()
+        .or_else(|| markdown::find_summary(&content, 400))
+        .unwrap_or_else(|| title.clone());
+    let metadata = std::fs::metadata(&audio)?;
+    let published_at = manifest.published_at(&document, metadata.modified()?)?;
+
+    Ok(Some(feed::Episode {
+        number: episode_number(&front_matter, &document),
+        guid: document,
+        title,
+        description,
+        size: metadata.len(),
+        duration: probe::duration(&audio)?,
+        published: SystemTime::UNIX_EPOCH + Duration::from_secs(published_at),
+        file: audio,
+    }))
+}
+
 // Function to process all steps
 async fn process_all(
     files: &[PathBuf],
     markdown_processor: &MarkdownProcessor,
     conversation_generator: &ConversationGenerator,
     audio_generator: &AudioGenerator,
-    input_path: &Path,
-    output_path: &Path,
+    config: &config::Config,
+    manifest: &Manifest,
+    report: &FailureReport,
 ) -> Result<()> {
     let start_time = Instant::now();
+    let output_path = &config.output.audio_path;
+    let limits = &config.concurrency;
 
     // Generate conversations
     let conv_start = Instant::now();
-    generate_conversations(files, markdown_processor, conversation_generator).await?;
+    generate_conversations(
+        files,
+        markdown_processor,
+        conversation_generator,
+        manifest,
+        limits.llm(&conversation_generator.model_type),
+        report,
+    )
+    .await?;
     println!(
         "Conversation generation took {}",
         format_elapsed(conv_start.elapsed())
@@ -486,7 +1338,17 @@ async fn process_all(
 
     // Generate audio from conversations
     let audio_start = Instant::now();
-    generate_audio_from_conversations(files, output_path, audio_generator).await?;
+    let files = report.remaining(files);
+    generate_audio_from_conversations(
+        &files,
+        output_path,
+        audio_generator,
+        &config.prompt,
+        manifest,
+        limits.tts,
+        report,
+    )
+    .await?;
     println!(
         "Audio generation took {}",
         format_elapsed(audio_start.elapsed())
@@ -494,7 +1356,16 @@ async fn process_all(
 
     // Generate intros
     let intro_start = Instant::now();
-    generate_intros(files, output_path, audio_generator).await?;
+    let files = report.remaining(&files);
+    generate_intros(
+        &files,
+        output_path,
+        audio_generator,
+        manifest,
+        limits.tts,
+        report,
+    )
+    .await?;
     println!(
         "Intro generation took {}",
         format_elapsed(intro_start.elapsed())
@@ -502,12 +1373,57 @@ async fn process_all(
 
     // Merge audio files
     let merge_start = Instant::now();
-    merge_audio_files(files, input_path, output_path)?;
+    let files = report.remaining(&files);
+    merge_audio_files(
+        &files,
+        output_path,
+        audio_generator.format(),
+        &config.merge,
+        manifest,
+        limits.merge,
+        report,
+    )
+    .await?;
     println!(
         "Audio merging took {}",
         format_elapsed(merge_start.elapsed())
     );
 
+    // Normalize loudness
+    if config.loudness.enabled {
+        let loudness_start = Instant::now();
+        let files = report.remaining(&files);
+        normalize_episodes(
+            &files,
+            output_path,
+            audio_generator.format(),
+            &config.loudness,
+            manifest,
+            limits.merge,
+            report,
+        )
+        .await?;
+        println!(
+            "Loudness normalization took {}",
+            format_elapsed(loudness_start.elapsed())
+        );
+    }
+
+    // Tag episodes, after normalizing as that rewrites the frames without their tags
+    if config.tags.enabled {
+        let files = report.remaining(&files);
+        tag_episodes(
+            &files,
+            markdown_processor,
+            audio_generator.format(),
+            config,
+            manifest,
+            limits.merge,
+            report,
+        )
+        .await?;
+    }
+
     let total_elapsed = start_time.elapsed();
     println!(
         "Full processing complete in {}",
@@ -520,4 +1436,15 @@ async fn process_all(
 mod audio;
 mod config;
 mod conversation;
+mod feed;
+mod id3;
+mod loudness;
+mod manifest;
 mod markdown;
+mod mp3;
+mod openai;
+mod probe;
+mod prompts;
+mod report;
+mod retry;
+mod stream;
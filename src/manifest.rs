@@ -0,0 +1,287 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::probe::AudioInfo;
+
+const MANIFEST_FILE: &str = "manifest.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Stage {
+    Conversation,
+    Audio,
+    Intro,
+    Merge,
+    Loudness,
+    Tags,
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Stage::Conversation => "conversation",
+            Stage::Audio => "audio",
+            Stage::Intro => "intro",
+            Stage::Merge => "merge",
+            Stage::Loudness => "loudness",
+            Stage::Tags => "tags",
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StageStatus {
+    // Still marked running on the next start means the previous run died mid-stage
+    Running,
+    Done,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StageRecord {
+    pub status: StageStatus,
+    // Hash of everything the stage output depends on, a different hash means redo
+    pub input_hash: String,
+    pub started_at: u64,
+    pub finished_at: Option<u64>,
+    pub error: Option<String>,
+    // What the stage's audio output decoded to
+    #[serde(default)]
+    pub audio: Option<AudioInfo>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DocumentEntry {
+    pub source_hash: Option<String>,
+    pub conversation_hash: Option<String>,
+    pub model: Option<String>,
+    pub voice: Option<String>,
+    #[serde(default)]
+    pub stages: BTreeMap<Stage, StageRecord>,
+    pub loudness: Option<LoudnessRecord>,
+    // Milliseconds of speech per conversation turn, where the episode's chapter markers go
+    #[serde(default)]
+    pub turn_durations_ms: Vec<u64>,
+    // When the episode was first merged, its date in the feed. Later stages rewrite the file,
+    // so its modification time says nothing about when it came out.
+    #[serde(default)]
+    pub published_at: Option<u64>,
+}
+
+impl DocumentEntry {
+    pub fn record_audio(&mut self, stage: Stage, info: AudioInfo) {
+        if let Some(record) = self.stages.get_mut(&stage) {
+            record.audio = Some(info);
+        }
+    }
+}
+
+// What the loudness stage measured on the merged episode and what it changed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LoudnessRecord {
+    pub target_lufs: f64,
+    pub measured_lufs: f64,
+    pub measured_true_peak_dbtp: f64,
+    pub gain_db: f64,
+    pub output_lufs: f64,
+    pub output_true_peak_dbtp: f64,
+}
+
+// Per-document pipeline state kept in the output directory between runs
+pub struct Manifest {
+    path: PathBuf,
+    documents: Mutex<BTreeMap<String, DocumentEntry>>,
+}
+
+impl Manifest {
+    pub fn load(output_dir: &Path) -> Result<Self> {
+        let path = output_dir.join(MANIFEST_FILE);
+        let documents = if path.exists() {
+            serde_json::from_str(&std::fs::read_to_string(&path)?)?
+        } else {
+            BTreeMap::new()
+        };
+
+        Ok(Self {
+            path,
+            documents: Mutex::new(documents),
+        })
+    }
+
+    // A stage is up to date when it finished with the same inputs and its output is still there.
+    // Outputs without a record are redone, nothing says what they were made from.
+    pub fn is_up_to_date(
+        &self,
+        document: &str,
+        stage: Stage,
+        input_hash: &str,
+        output: &Path,
+    ) -> Result<bool> {
+        if !output.exists() {
+            return Ok(false);
+        }
+
+        let documents = self.lock();
+        let record = documents
+            .get(document)
+            .and_then(|entry| entry.stages.get(&stage));
+        Ok(record.is_some_and(|record| {
+            record.status == StageStatus::Done && record.input_hash == input_hash
+        }))
+    }
+
+    pub fn entry(&self, document: &str) -> Option<DocumentEntry> {
+        self.lock().get(document).cloned()
+    }
+
+    // Seconds since the epoch, the fallback is only recorded for episodes merged before the
+    // manifest kept a date
+    pub fn published_at(&self, document: &str, fallback: SystemTime) -> Result<u64> {
+        let mut documents = self.lock();
+        let entry = documents.entry(document.to_string()).or_default();
+        if let Some(published_at) = entry.published_at {
+            return Ok(published_at);
+        }
+        let published_at = seconds(fallback);
+        entry.published_at = Some(published_at);
+        self.save(&documents)?;
+        Ok(published_at)
+    }
+
+    pub fn start(&self, document: &str, stage: Stage, input_hash: &str) -> Result<()> {
+        let mut documents = self.lock();
+        let entry = documents.entry(document.to_string()).or_default();
+        entry.stages.insert(
+            stage,
+            StageRecord {
+                status: StageStatus::Running,
+                input_hash: input_hash.to_string(),
+                started_at: now(),
+                finished_at: None,
+                error: None,
+                audio: None,
+            },
+        );
+        self.save(&documents)
+    }
+
+    pub fn finish(
+        &self,
+        document: &str,
+        stage: Stage,
+        update: impl FnOnce(&mut DocumentEntry),
+    ) -> Result<()> {
+        let mut documents = self.lock();
+        let entry = documents.entry(document.to_string()).or_default();
+        update(entry);
+        if let Some(record) = entry.stages.get_mut(&stage) {
+            record.status = StageStatus::Done;
+            record.finished_at = Some(now());
+        }
+        self.save(&documents)
+    }
+
+    pub fn fail(&self, document: &str, stage: Stage, error: &anyhow::Error) -> Result<()> {
+        let mut documents = self.lock();
+        if let Some(record) = documents
+            .get_mut(document)
+            .and_then(|entry| entry.stages.get_mut(&stage))
+        {
+            record.status = StageStatus::Failed;
+            record.finished_at = Some(now());
+            record.error = Some(format!("{:#}", error));
+        }
+        self.save(&documents)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BTreeMap<String, DocumentEntry>> {
+        self.documents
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    // Written through a temp file so a crash never leaves a truncated manifest behind
+    fn save(&self, documents: &BTreeMap<String, DocumentEntry>) -> Result<()> {
+        let temp = self.path.with_extension("json.tmp");
+        std::fs::write(&temp, serde_json::to_string_pretty(documents)?)?;
+        std::fs::rename(&temp, &self.path)?;
+        Ok(())
+    }
+}
+
+// Hex sha256 over all parts, length-prefixed so ("ab", "c") and ("a", "bc") differ
+pub fn hash(parts: &[&[u8]]) -> String {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.update((part.len() as u64).to_le_bytes());
+        hasher.update(part);
+    }
+    hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+pub fn now() -> u64 {
+    seconds(SystemTime::now())
+}
+
+fn seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn up_to_date_only_when_done_with_the_same_inputs() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("manifest-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let output = dir.join("doc.conversation.txt");
+        std::fs::write(&output, "Alice: Hi")?;
+        let manifest = Manifest::load(&dir)?;
+        let stage = Stage::Conversation;
+
+        // No record, the output could have been made from anything
+        assert!(!manifest.is_up_to_date("doc", stage, "a", &output)?);
+
+        // Running means the run that started it died, failed means it never produced the output
+        manifest.start("doc", stage, "a")?;
+        assert!(!manifest.is_up_to_date("doc", stage, "a", &output)?);
+        manifest.fail("doc", stage, &anyhow::anyhow!("timed out"))?;
+        assert!(!manifest.is_up_to_date("doc", stage, "a", &output)?);
+
+        manifest.start("doc", stage, "a")?;
+        manifest.finish("doc", stage, |_| {})?;
+        assert!(manifest.is_up_to_date("doc", stage, "a", &output)?);
+        assert!(!manifest.is_up_to_date("doc", stage, "b", &output)?);
+        assert!(!manifest.is_up_to_date("doc", Stage::Audio, "a", &output)?);
+
+        // The record survives a reload, the output has to still be there
+        let manifest = Manifest::load(&dir)?;
+        assert!(manifest.is_up_to_date("doc", stage, "a", &output)?);
+        std::fs::remove_file(&output)?;
+        assert!(!manifest.is_up_to_date("doc", stage, "a", &output)?);
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn hash_parts_are_length_prefixed() {
+        assert_ne!(hash(&[b"ab", b"c"]), hash(&[b"a", b"bc"]));
+        assert_eq!(hash(&[b"ab", b"c"]), hash(&[b"ab", b"c"]));
+    }
+}
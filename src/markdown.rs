@@ -1,30 +1,755 @@
-// Will implement markdown processing later
-
+use crate::config::InputConfig;
 use crate::{MarkdownProcessing, MarkdownProcessor};
-use anyhow::Result;
+use anyhow::{anyhow, Context, Result};
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use pulldown_cmark::{CodeBlockKind, Event, LinkType, Options, Parser, Tag, TagEnd};
+use serde::{Deserialize, Deserializer};
+use std::cmp::Ordering;
+use std::collections::HashMap;
 use std::fs;
+use std::iter::Peekable;
 use std::path::{Path, PathBuf};
-use walkdir::WalkDir;
+use std::str::Chars;
+use walkdir::{DirEntry, WalkDir};
 
-pub fn find_markdown_files(dir: &Path) -> Result<Vec<PathBuf>> {
-    let mut markdown_files = Vec::new();
+// Per-document overrides from a YAML (---) or TOML (+++) block at the top of the file
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct FrontMatter {
+    pub title: Option<String>,
+    #[serde(deserialize_with = "chapter_label")]
+    pub chapter: Option<String>,
+    pub speakers: Vec<String>,
+    // Speaker name to voice, "default" sets the voice for narration and unmapped speakers
+    pub voices: HashMap<String, String>,
+    pub skip: bool,
+    // Extra instructions appended to the system prompt for this document
+    pub prompt: Option<String>,
+    // Episode description in the podcast feed, defaults to the first paragraph
+    pub description: Option<String>,
+}
+
+// Chapters are usually numbers but labels like "7a" are fine too
+fn chapter_label<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Label {
+        Number(i64),
+        Text(String),
+    }
+
+    Ok(
+        Option::<Label>::deserialize(deserializer)?.map(|label| match label {
+            Label::Number(number) => number.to_string(),
+            Label::Text(text) => text,
+        }),
+    )
+}
+
+pub fn parse_front_matter(content: &str) -> Result<FrontMatter> {
+    split_front_matter(content).map(|(front_matter, _)| front_matter)
+}
+
+// The front matter and the document after it. A --- block that isn't a mapping, like prose
+// after a thematic break, belongs to the document.
+fn split_front_matter(content: &str) -> Result<(FrontMatter, &str)> {
+    let content = content.trim_start_matches('\u{feff}');
+    let mut lines = content.split_inclusive('\n');
+    let first = lines.next().unwrap_or_default();
+    let fence = match first.trim_end() {
+        fence @ ("---" | "+++") => fence,
+        _ => return Ok((FrontMatter::default(), content)),
+    };
 
-    for entry in WalkDir::new(dir)
-        .follow_links(true)
+    let mut block = Vec::new();
+    let mut offset = first.len();
+    let mut body = None;
+    for line in lines {
+        offset += line.len();
+        let line = line.trim_end();
+        if line == fence || (fence == "---" && line == "...") {
+            body = Some(&content[offset..]);
+            break;
+        }
+        block.push(line);
+    }
+    let Some(body) = body else {
+        return Err(anyhow!(
+            "Front matter starting with {} is never closed",
+            fence
+        ));
+    };
+
+    let block = block.join("\n");
+    if block.trim().is_empty() {
+        return Ok((FrontMatter::default(), body));
+    }
+    if fence == "+++" {
+        return Ok((toml::from_str(&block)?, body));
+    }
+    match serde_yaml::from_str(&block)? {
+        value @ serde_yaml::Value::Mapping(_) => Ok((serde_yaml::from_value(value)?, body)),
+        _ => Ok((FrontMatter::default(), content)),
+    }
+}
+
+pub fn read_front_matter(file_path: &Path) -> Result<FrontMatter> {
+    let content = fs::read_to_string(file_path)?;
+    parse_front_matter(&content)
+        .with_context(|| format!("Invalid front matter in {}", file_path.display()))
+}
+
+pub fn find_markdown_files(input: &InputConfig) -> Result<Vec<PathBuf>> {
+    let include = glob_set(&input.include)?;
+    let exclude = glob_set(&input.exclude)?;
+    let relative = |path: &Path| {
+        path.strip_prefix(&input.docs_path)
+            .unwrap_or(path)
+            .to_path_buf()
+    };
+
+    let mut walker = WalkDir::new(&input.docs_path).follow_links(true);
+    if let Some(max_depth) = input.max_depth {
+        walker = walker.max_depth(max_depth);
+    }
+
+    let mut markdown_files = Vec::new();
+    for entry in walker
         .into_iter()
+        .filter_entry(|entry| entry.depth() == 0 || input.include_hidden || !is_hidden(entry))
         .filter_map(|e| e.ok())
+        .filter(|entry| entry.file_type().is_file())
     {
-        let path = entry.path();
-        if path.extension().map_or(false, |ext| ext == "md") {
-            markdown_files.push(path.to_path_buf());
+        let path = relative(entry.path());
+        if include.is_match(&path) && !exclude.is_match(&path) {
+            markdown_files.push(entry.into_path());
         }
     }
 
+    // Sorted so the menu order and chapter numbering don't depend on the filesystem
+    markdown_files.sort_by(|a, b| {
+        natural_cmp(
+            &relative(a).to_string_lossy(),
+            &relative(b).to_string_lossy(),
+        )
+    });
     Ok(markdown_files)
 }
 
+// Case-insensitive, and "*" stops at "/" like in .gitignore
+fn glob_set(patterns: &[String]) -> Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = GlobBuilder::new(pattern)
+            .case_insensitive(true)
+            .literal_separator(true)
+            .build()
+            .with_context(|| format!("Invalid glob pattern: {}", pattern))?;
+        builder.add(glob);
+    }
+    Ok(builder.build()?)
+}
+
+fn is_hidden(entry: &DirEntry) -> bool {
+    entry.file_name().to_string_lossy().starts_with('.')
+}
+
+// Orders runs of digits by value so that "2.md" comes before "10.md"
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a_chars = a.chars().peekable();
+    let mut b_chars = b.chars().peekable();
+
+    loop {
+        match (a_chars.peek(), b_chars.peek()) {
+            (None, None) => return a.cmp(b),
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let x = take_number(&mut a_chars);
+                let y = take_number(&mut b_chars);
+                let ordering = x.len().cmp(&y.len()).then_with(|| x.cmp(&y));
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+            (Some(x), Some(y)) => {
+                let ordering = x.to_lowercase().cmp(y.to_lowercase());
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+                a_chars.next();
+                b_chars.next();
+            }
+        }
+    }
+}
+
+// Consumes a run of digits, without leading zeros so that the length orders by value
+fn take_number(chars: &mut Peekable<Chars>) -> String {
+    let mut digits = String::new();
+    while let Some(c) = chars.next_if(char::is_ascii_digit) {
+        digits.push(c);
+    }
+    digits.trim_start_matches('0').to_string()
+}
+
+// First top-level heading of the document, if it has one
+pub fn find_title(content: &str) -> Option<String> {
+    content
+        .lines()
+        .find_map(|line| line.strip_prefix("# "))
+        .map(|title| title.trim().to_string())
+        .filter(|title| !title.is_empty())
+}
+
+// First heading of any level, such as the one a chunk of the document starts with
+pub fn find_heading(content: &str) -> Option<String> {
+    content
+        .lines()
+        .map(str::trim_start)
+        .find_map(|line| {
+            let text = line.trim_start_matches('#');
+            (text.len() < line.len() && text.starts_with(' ')).then(|| text.trim().to_string())
+        })
+        .filter(|heading| !heading.is_empty())
+}
+
+// First paragraph of prose, shortened on a word boundary to at most max_chars
+pub fn find_summary(content: &str, max_chars: usize) -> Option<String> {
+    let paragraph = content
+        .split("\n\n")
+        .map(|paragraph| paragraph.split_whitespace().collect::<Vec<_>>().join(" "))
+        .find(|paragraph| !paragraph.is_empty() && !paragraph.starts_with('#'))?;
+
+    if paragraph.chars().count() <= max_chars {
+        return Some(paragraph);
+    }
+    let cut: String = paragraph.chars().take(max_chars).collect();
+    let cut = cut.rsplit_once(' ').map_or(cut.as_str(), |(head, _)| head);
+    Some(format!("{}…", cut.trim_end_matches([',', ';', ':', '.'])))
+}
+
+// Group heading sections into chunks of at most max_chars, splitting oversized sections by paragraph
+pub fn split_into_chunks(content: &str, max_chars: usize) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+
+    for section in split_sections(content) {
+        for piece in split_oversized(&section, max_chars) {
+            if !current.is_empty() && chars(&current) + chars(&piece) > max_chars {
+                chunks.push(std::mem::take(&mut current));
+            }
+            current.push_str(&piece);
+        }
+    }
+
+    if !current.trim().is_empty() {
+        chunks.push(current);
+    }
+
+    chunks
+}
+
+// Each section starts at a heading line, headings inside code fences don't count
+fn split_sections(content: &str) -> Vec<String> {
+    let mut sections = Vec::new();
+    let mut current = String::new();
+    let mut in_fence = false;
+
+    for line in content.split_inclusive('\n') {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_fence = !in_fence;
+        }
+
+        if !in_fence && trimmed.starts_with('#') && !current.trim().is_empty() {
+            sections.push(std::mem::take(&mut current));
+        }
+
+        current.push_str(line);
+    }
+
+    if !current.trim().is_empty() {
+        sections.push(current);
+    }
+
+    sections
+}
+
+fn chars(text: &str) -> usize {
+    text.chars().count()
+}
+
+fn split_oversized(section: &str, max_chars: usize) -> Vec<String> {
+    if chars(section) <= max_chars {
+        return vec![section.to_string()];
+    }
+
+    let mut pieces = Vec::new();
+    let mut current = String::new();
+
+    for paragraph in section.split_inclusive("\n\n") {
+        if !current.is_empty() && chars(&current) + chars(paragraph) > max_chars {
+            pieces.push(std::mem::take(&mut current));
+        }
+
+        if chars(paragraph) > max_chars {
+            // Last resort for a single huge paragraph, cut on character boundaries
+            let chars: Vec<char> = paragraph.chars().collect();
+            pieces.extend(
+                chars
+                    .chunks(max_chars.max(1))
+                    .map(|chunk| chunk.iter().collect::<String>()),
+            );
+        } else {
+            current.push_str(paragraph);
+        }
+    }
+
+    if !current.is_empty() {
+        pieces.push(current);
+    }
+
+    pieces
+}
+
+// Code blocks are read by nobody, the model only hears that one was there
+struct CodeBlock {
+    language: Option<String>,
+    lines: usize,
+}
+
+#[derive(Default)]
+struct Table {
+    headers: Vec<String>,
+    row: Vec<String>,
+    cell: String,
+}
+
+// Renders parsed markdown as plain text that reads well aloud. Headings stay as "#" lines so
+// titles and section chunking keep working on the cleaned text.
+#[derive(Default)]
+struct SpeechWriter {
+    out: String,
+    // Item counters of the open lists, None for bullet lists
+    lists: Vec<Option<u64>>,
+    table: Option<Table>,
+    code: Option<CodeBlock>,
+    // Whether each open link is skipped, link text is kept but bare urls are not
+    links: Vec<bool>,
+    // Depth of elements whose text is dropped: front matter, html, images, footnotes, autolinks
+    skip: usize,
+}
+
+impl SpeechWriter {
+    fn event(&mut self, event: Event) {
+        match event {
+            Event::Start(tag) => self.start(tag),
+            Event::End(tag) => self.end(tag),
+            Event::Text(text) => match &mut self.code {
+                Some(code) => code.lines += text.lines().count(),
+                None => self.push(&text),
+            },
+            Event::Code(text) => self.push(&text),
+            Event::SoftBreak => self.push(" "),
+            Event::HardBreak => self.newline(),
+            Event::Rule => self.blank_line(),
+            _ => {}
+        }
+    }
+
+    fn start(&mut self, tag: Tag) {
+        match tag {
+            Tag::Heading { level, .. } => {
+                self.blank_line();
+                self.out.push_str(&"#".repeat(level as usize));
+                self.out.push(' ');
+            }
+            Tag::CodeBlock(kind) => {
+                let language = match kind {
+                    CodeBlockKind::Fenced(info) => info
+                        .split_whitespace()
+                        .next()
+                        .map(|language| language.to_string()),
+                    CodeBlockKind::Indented => None,
+                };
+                self.code = Some(CodeBlock { language, lines: 0 });
+            }
+            Tag::List(first) => {
+                if self.lists.is_empty() {
+                    self.blank_line();
+                }
+                self.lists.push(first);
+            }
+            Tag::Item => {
+                self.newline();
+                let depth = self.lists.len().saturating_sub(1);
+                self.out.push_str(&"  ".repeat(depth));
+                match self.lists.last_mut() {
+                    Some(Some(number)) => {
+                        self.out.push_str(&format!("{}. ", number));
+                        *number += 1;
+                    }
+                    _ => self.out.push_str("- "),
+                }
+            }
+            Tag::Table(_) => {
+                self.blank_line();
+                self.table = Some(Table::default());
+            }
+            Tag::TableCell => {
+                if let Some(table) = &mut self.table {
+                    table.cell.clear();
+                }
+            }
+            Tag::Link { link_type, .. } => {
+                let bare = matches!(link_type, LinkType::Autolink | LinkType::Email);
+                self.links.push(bare);
+                if bare {
+                    self.skip += 1;
+                }
+            }
+            Tag::Image { .. } | Tag::HtmlBlock | Tag::FootnoteDefinition(_) => self.skip += 1,
+            _ => {}
+        }
+    }
+
+    fn end(&mut self, tag: TagEnd) {
+        match tag {
+            TagEnd::Paragraph | TagEnd::BlockQuote(_) => {
+                if self.lists.is_empty() {
+                    self.blank_line();
+                } else {
+                    self.newline();
+                }
+            }
+            TagEnd::Heading(_) => self.blank_line(),
+            TagEnd::CodeBlock => {
+                if let Some(code) = self.code.take() {
+                    self.blank_line();
+                    self.out.push_str(&match code.language {
+                        Some(language) => {
+                            format!("(A {} code example of {} lines.)", language, code.lines)
+                        }
+                        None => format!("(A code example of {} lines.)", code.lines),
+                    });
+                    self.blank_line();
+                }
+            }
+            TagEnd::List(_) => {
+                self.lists.pop();
+                if self.lists.is_empty() {
+                    self.blank_line();
+                }
+            }
+            TagEnd::TableCell => {
+                if let Some(table) = &mut self.table {
+                    let cell = std::mem::take(&mut table.cell);
+                    table.row.push(cell.trim().to_string());
+                }
+            }
+            TagEnd::TableHead => {
+                if let Some(table) = &mut self.table {
+                    table.headers = std::mem::take(&mut table.row);
+                }
+            }
+            TagEnd::TableRow => {
+                if let Some(table) = &mut self.table {
+                    let row = std::mem::take(&mut table.row);
+                    let line = table_row(&table.headers, &row);
+                    if !line.is_empty() {
+                        self.out.push_str(&format!("- {}\n", line));
+                    }
+                }
+            }
+            TagEnd::Table => {
+                self.table = None;
+                self.blank_line();
+            }
+            TagEnd::Link => self.skip -= usize::from(self.links.pop() == Some(true)),
+            TagEnd::Image | TagEnd::HtmlBlock | TagEnd::FootnoteDefinition => self.skip -= 1,
+            _ => {}
+        }
+    }
+
+    fn push(&mut self, text: &str) {
+        if self.skip > 0 {
+            return;
+        }
+        match &mut self.table {
+            Some(table) => table.cell.push_str(text),
+            None => self.out.push_str(text),
+        }
+    }
+
+    fn newline(&mut self) {
+        if !self.out.is_empty() && !self.out.ends_with('\n') {
+            self.out.push('\n');
+        }
+    }
+
+    fn blank_line(&mut self) {
+        self.newline();
+        if !self.out.is_empty() && !self.out.ends_with("\n\n") {
+            self.out.push('\n');
+        }
+    }
+}
+
+// "Header: value" pairs so a table row can be read out as a sentence
+fn table_row(headers: &[String], cells: &[String]) -> String {
+    cells
+        .iter()
+        .enumerate()
+        .filter(|(_, cell)| !cell.is_empty())
+        .map(|(index, cell)| match headers.get(index) {
+            Some(header) if !header.is_empty() => format!("{}: {}", header, cell),
+            _ => cell.clone(),
+        })
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+// Strip what the model shouldn't spend its context on: front matter, html, urls and code
+pub fn speech_text(content: &str) -> String {
+    // Front matter that doesn't parse is reported by the stages, here it is left in the text
+    let body = split_front_matter(content).map_or(content, |(_, body)| body);
+    let options =
+        Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH | Options::ENABLE_FOOTNOTES;
+
+    let mut writer = SpeechWriter::default();
+    for event in Parser::new_ext(body, options) {
+        writer.event(event);
+    }
+
+    let text = writer.out.trim();
+    if text.is_empty() {
+        String::new()
+    } else {
+        format!("{}\n", text)
+    }
+}
+
 impl MarkdownProcessing for MarkdownProcessor {
     fn process_markdown(&self, file_path: &Path) -> Result<String> {
-        fs::read_to_string(file_path).map_err(Into::into)
+        let content = fs::read_to_string(file_path)?;
+        Ok(speech_text(&content))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn natural_order_compares_numbers_by_value() {
+        let mut names = vec![
+            "10.md",
+            "b.md",
+            "2.md",
+            "02.md",
+            "A.md",
+            "nip-10.md",
+            "NIP-9.md",
+            "1.md",
+            "c.md",
+        ];
+        names.sort_by(|a, b| natural_cmp(a, b));
+        assert_eq!(
+            names,
+            [
+                "1.md",
+                "02.md",
+                "2.md",
+                "10.md",
+                "A.md",
+                "b.md",
+                "c.md",
+                "NIP-9.md",
+                "nip-10.md"
+            ]
+        );
+    }
+
+    #[test]
+    fn finds_markdown_files_in_natural_order() -> Result<()> {
+        // A hidden root is still walked, only what is below it can be hidden
+        let root = std::env::temp_dir().join(format!(".find-test-{}", std::process::id()));
+        let files = [
+            "10.md",
+            "2.md",
+            "01-intro.md",
+            "Beta.md",
+            "alpha.md",
+            "notes.txt",
+            "README.md",
+            "guide/readme.md",
+            "guide/CHANGELOG.md",
+            "guide/3.md",
+            "node_modules/pkg/doc.md",
+            ".hidden.md",
+            ".drafts/draft.md",
+        ];
+        for file in files {
+            let path = root.join(file);
+            fs::create_dir_all(path.parent().unwrap())?;
+            fs::write(path, "# Title\n")?;
+        }
+
+        let find = |extra: &str| -> Result<Vec<String>> {
+            let input: InputConfig =
+                toml::from_str(&format!("docs_path = '{}'\n{}", root.display(), extra))?;
+            Ok(find_markdown_files(&input)?
+                .iter()
+                .map(|path| {
+                    let relative = path.strip_prefix(&root).unwrap();
+                    relative.to_string_lossy().replace('\\', "/")
+                })
+                .collect())
+        };
+
+        assert_eq!(
+            find("")?,
+            [
+                "01-intro.md",
+                "2.md",
+                "10.md",
+                "alpha.md",
+                "Beta.md",
+                "guide/3.md"
+            ]
+        );
+        assert_eq!(
+            find("include_hidden = true")?,
+            [
+                ".drafts/draft.md",
+                ".hidden.md",
+                "01-intro.md",
+                "2.md",
+                "10.md",
+                "alpha.md",
+                "Beta.md",
+                "guide/3.md"
+            ]
+        );
+        assert_eq!(
+            find("max_depth = 1")?,
+            ["01-intro.md", "2.md", "10.md", "alpha.md", "Beta.md"]
+        );
+        assert_eq!(
+            find("include = ['guide/*.md', 'notes.txt']\nexclude = []")?,
+            [
+                "guide/3.md",
+                "guide/CHANGELOG.md",
+                "guide/readme.md",
+                "notes.txt"
+            ]
+        );
+
+        fs::remove_dir_all(&root)?;
+        Ok(())
+    }
+
+    #[test]
+    fn reads_yaml_front_matter() {
+        let content =
+            "---\ntitle: Relays\nchapter: 7\nspeakers: [Ann, Bob]\nskip: true\n---\n# Relays\n";
+        let front_matter = parse_front_matter(content).unwrap();
+
+        assert_eq!(front_matter.title.as_deref(), Some("Relays"));
+        assert_eq!(front_matter.chapter.as_deref(), Some("7"));
+        assert_eq!(front_matter.speakers, ["Ann", "Bob"]);
+        assert!(front_matter.skip);
+    }
+
+    #[test]
+    fn reads_toml_front_matter() {
+        let content = "+++\nchapter = \"7a\"\n+++\n# Relays\n";
+        let front_matter = parse_front_matter(content).unwrap();
+        assert_eq!(front_matter.chapter.as_deref(), Some("7a"));
+    }
+
+    #[test]
+    fn thematic_break_is_not_front_matter() {
+        let content = "---\nAn opening paragraph.\n\n---\n# Relays\n";
+        let front_matter = parse_front_matter(content).unwrap();
+        assert!(front_matter.title.is_none());
+        assert!(!front_matter.skip);
+    }
+
+    #[test]
+    fn speech_text_leaves_out_front_matter_only() {
+        let yaml = "---\ntitle: Relays\n---\n# Relays\n\nBody text.\n";
+        assert_eq!(speech_text(yaml), "# Relays\n\nBody text.\n");
+        let toml = "+++\ntitle = \"Relays\"\n+++\nBody text.\n";
+        assert_eq!(speech_text(toml), "Body text.\n");
+
+        let text = speech_text("---\nAn opening paragraph.\n\n---\n# Relays\n\nBody text.\n");
+        assert!(text.contains("An opening paragraph."), "{}", text);
+        assert!(text.contains("Body text."), "{}", text);
+    }
+
+    // Every chunk fits and nothing is lost, apart from a trailing chunk of only whitespace
+    fn check_chunks(content: &str, max_chars: usize) -> Vec<String> {
+        let chunks = split_into_chunks(content, max_chars);
+        for chunk in &chunks {
+            assert!(chars(chunk) <= max_chars, "{:?}", chunk);
+        }
+        assert_eq!(
+            chunks.concat().trim_end(),
+            content.trim_end(),
+            "max {}",
+            max_chars
+        );
+        chunks
+    }
+
+    #[test]
+    fn chunks_start_at_headings_outside_code_fences() {
+        let content =
+            "# Events\nSigned JSON.\n```\n# not a heading\n```\nMore.\n# Relays\nStore events.\n";
+        let chunks = check_chunks(content, 60);
+        assert_eq!(chunks.len(), 2);
+        assert!(chunks[0].contains("# not a heading\n```\nMore."));
+        assert!(chunks[1].starts_with("# Relays"));
+
+        // Sections that fit together share a chunk
+        assert_eq!(check_chunks(content, 1000).len(), 1);
+    }
+
+    #[test]
+    fn oversized_sections_split_by_paragraph_then_by_character() {
+        let paragraphs = "# Events\nFirst paragraph here.\n\nSecond paragraph here.\n\nThird.\n";
+        assert_eq!(
+            check_chunks(paragraphs, 32),
+            [
+                "# Events\nFirst paragraph here.\n\n",
+                "Second paragraph here.\n\nThird.\n"
+            ]
+        );
+
+        let huge = format!("# Long\n{}\n", "word ".repeat(100));
+        let chunks = check_chunks(&huge, 120);
+        assert!(chunks.len() >= 5);
+    }
+
+    #[test]
+    fn chunks_never_exceed_the_limit() {
+        let mut content = String::new();
+        for section in 0..30 {
+            content.push_str(&format!("## Section {}\n", section));
+            for paragraph in 0..section % 7 {
+                content.push_str(&"Größe café 日本語 text. ".repeat(paragraph * 5 + 1));
+                content.push_str("\n\n");
+            }
+        }
+        for max_chars in [1, 10, 64, 100, 500, 4000, 100_000] {
+            check_chunks(&content, max_chars);
+        }
+    }
+
+    #[test]
+    fn unclosed_front_matter_is_an_error() {
+        assert!(parse_front_matter("---\ntitle: Relays\n# Relays\n").is_err());
     }
 }
@@ -0,0 +1,304 @@
+// Just enough of MPEG audio layer III to edit frames without decoding and re-encoding them:
+// the header, and the global_gain fields in the side info that scale each granule.
+
+// One global_gain step changes the amplitude by 2^(1/4)
+pub const GAIN_STEP_DB: f64 = 1.505;
+
+struct FrameInfo {
+    mpeg1: bool,
+    channels: usize,
+    crc: bool,
+}
+
+fn frame_info(frame: &[u8]) -> Option<FrameInfo> {
+    if frame.len() < 4 || frame[0] != 0xFF || frame[1] & 0xE0 != 0xE0 {
+        return None;
+    }
+    // Version 3 is MPEG-1, 2 and 0 are MPEG-2 and 2.5, layer 1 is layer III
+    let version = (frame[1] >> 3) & 0b11;
+    if version == 1 || (frame[1] >> 1) & 0b11 != 1 {
+        return None;
+    }
+    Some(FrameInfo {
+        mpeg1: version == 3,
+        channels: if frame[3] >> 6 == 3 { 1 } else { 2 },
+        crc: frame[1] & 1 == 0,
+    })
+}
+
+impl FrameInfo {
+    fn side_info_start(&self) -> usize {
+        if self.crc {
+            6
+        } else {
+            4
+        }
+    }
+
+    fn side_info_len(&self) -> usize {
+        match (self.mpeg1, self.channels) {
+            (true, 1) => 17,
+            (true, _) => 32,
+            (false, 1) => 9,
+            (false, _) => 17,
+        }
+    }
+
+    // Bit offsets of global_gain in the side info, one per granule and channel
+    fn global_gain_offsets(&self) -> Vec<usize> {
+        let mono = self.channels == 1;
+        let (start, granules, block_bits) = if self.mpeg1 {
+            // main_data_begin, private bits and the scalefactor selection per channel
+            (9 + if mono { 5 } else { 3 } + 4 * self.channels, 2, 59)
+        } else {
+            (8 + if mono { 1 } else { 2 }, 1, 63)
+        };
+        // part2_3_length and big_values come first in every block
+        (0..granules * self.channels)
+            .map(|block| start + block * block_bits + 21)
+            .collect()
+    }
+}
+
+pub fn samples_per_frame(frame: &[u8]) -> usize {
+    match frame_info(frame) {
+        Some(info) if !info.mpeg1 => 576,
+        _ => 1152,
+    }
+}
+
+// A frame with the same header that decodes to silence: zeroed side info means no audio data
+pub fn silent_frame(template: &[u8]) -> Vec<u8> {
+    let mut frame = vec![0; template.len()];
+    frame[..4].copy_from_slice(&template[..4]);
+    // Without a CRC the zeros after the header are all side info and main data
+    frame[1] |= 1;
+    // The padding bit stays as well, it matches the template's length
+    frame
+}
+
+// Changes the level of the frame by steps * GAIN_STEP_DB, negative steps make it quieter
+pub fn adjust_gain(frame: &mut [u8], steps: i32) {
+    let Some(info) = frame_info(frame) else {
+        return;
+    };
+    let start = info.side_info_start();
+    let end = start + info.side_info_len();
+    if frame.len() < end {
+        return;
+    }
+
+    let side_info = &mut frame[start..end];
+    for offset in info.global_gain_offsets() {
+        let gain = read_bits(side_info, offset, 8) as i32;
+        write_bits(side_info, offset, 8, (gain + steps).clamp(0, 255) as u32);
+    }
+
+    if info.crc {
+        let crc = crc16(&[&frame[2..4], &frame[start..end]]);
+        frame[4..6].copy_from_slice(&crc.to_be_bytes());
+    }
+}
+
+fn read_bits(bytes: &[u8], offset: usize, count: usize) -> u32 {
+    (offset..offset + count).fold(0, |value, bit| {
+        (value << 1) | ((bytes[bit / 8] >> (7 - bit % 8)) & 1) as u32
+    })
+}
+
+fn write_bits(bytes: &mut [u8], offset: usize, count: usize, value: u32) {
+    for (index, bit) in (offset..offset + count).enumerate() {
+        let mask = 1 << (7 - bit % 8);
+        if (value >> (count - 1 - index)) & 1 == 1 {
+            bytes[bit / 8] |= mask;
+        } else {
+            bytes[bit / 8] &= !mask;
+        }
+    }
+}
+
+// CRC-16 with polynomial 0x8005 over the last two header bytes and the side info
+fn crc16(parts: &[&[u8]]) -> u16 {
+    let mut crc: u16 = 0xFFFF;
+    for byte in parts.iter().flat_map(|part| part.iter()) {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x8005
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{audio_merger, probe};
+
+    // Writes (bit count, value) fields one after the other
+    fn pack(len: usize, fields: &[(usize, u32)]) -> Vec<u8> {
+        let mut bytes = vec![0; len];
+        let mut offset = 0;
+        for &(count, value) in fields {
+            write_bits(&mut bytes, offset, count, value);
+            offset += count;
+        }
+        assert!(offset <= len * 8);
+        bytes
+    }
+
+    // Side info laid out field by field from the standard, with a global_gain per block
+    fn side_info(mpeg1: bool, channels: usize, gains: &[u32]) -> Vec<u8> {
+        let mono = channels == 1;
+        let mut fields = if mpeg1 {
+            vec![
+                (9, 0x1AB),
+                (if mono { 5 } else { 3 }, 0b101),
+                (4 * channels, 0b1010),
+            ]
+        } else {
+            vec![(8, 0xAB), (if mono { 1 } else { 2 }, 0b1)]
+        };
+        // The fields after global_gain, split so each fits in a u32
+        let rest = if mpeg1 { 30 } else { 34 } / 2;
+        for (block, gain) in gains.iter().enumerate() {
+            let pattern = 0x155555 >> block;
+            fields.extend([
+                (12, 1000 + block as u32),
+                (9, 300 + block as u32),
+                (8, *gain),
+                (rest, pattern & ((1 << rest) - 1)),
+                (rest, !pattern & ((1 << rest) - 1)),
+            ]);
+        }
+        let len = match (mpeg1, mono) {
+            (true, true) => 17,
+            (true, false) => 32,
+            (false, true) => 9,
+            (false, false) => 17,
+        };
+        pack(len, &fields)
+    }
+
+    #[test]
+    fn adjust_gain_changes_only_global_gain() {
+        let layouts = [
+            ([0xFF, 0xFB, 0x90, 0xC0], true, 1),
+            ([0xFF, 0xFB, 0x90, 0x00], true, 2),
+            ([0xFF, 0xF3, 0x84, 0xC0], false, 1),
+            ([0xFF, 0xF3, 0x84, 0x00], false, 2),
+        ];
+        for (header, mpeg1, channels) in layouts {
+            let blocks = channels * if mpeg1 { 2 } else { 1 };
+            let gains: Vec<u32> = (0..blocks as u32).map(|block| 100 + block).collect();
+            let raised: Vec<u32> = gains.iter().map(|gain| gain + 3).collect();
+
+            let frame = |gains: &[u32]| {
+                let mut frame = header.to_vec();
+                frame.extend(side_info(mpeg1, channels, gains));
+                frame.extend([0x5A; 16]);
+                frame
+            };
+            let mut adjusted = frame(&gains);
+            adjust_gain(&mut adjusted, 3);
+            assert_eq!(adjusted, frame(&raised), "{:02X?}", header);
+        }
+    }
+
+    #[test]
+    fn adjust_gain_clamps_to_the_field() {
+        let mut frame = vec![0xFF, 0xF3, 0x84, 0xC0];
+        frame.extend(side_info(false, 1, &[254]));
+        adjust_gain(&mut frame, 4);
+        assert_eq!(read_bits(&frame[4..], 30, 8), 255);
+        adjust_gain(&mut frame, -300);
+        assert_eq!(read_bits(&frame[4..], 30, 8), 0);
+    }
+
+    #[test]
+    fn crc16_check_value() {
+        assert_eq!(crc16(&[b"1234", b"56789"]), 0xAEE7);
+    }
+
+    // MPEG-2 mono frames at 24 kHz and 64 kbps with random main data
+    fn noise(crc: bool, frames: usize) -> Vec<u8> {
+        const LEN: usize = 192;
+        let start = if crc { 6 } else { 4 };
+        let mut rng = fastrand::Rng::with_seed(1);
+        let mut stream = Vec::new();
+        for _ in 0..frames {
+            let mut frame = vec![0xFF, if crc { 0xF2 } else { 0xF3 }, 0x84, 0xC0];
+            if crc {
+                frame.extend([0, 0]);
+            }
+            let main_data_bits = (LEN - start - 9) as u32 * 8;
+            frame.extend(pack(
+                9,
+                &[
+                    (8, 0),
+                    (1, 0),
+                    (12, main_data_bits),
+                    (9, 120),
+                    (8, 170),
+                    (9, 0),
+                    (1, 0),
+                    (5, 7),
+                    (5, 8),
+                    (5, 9),
+                    (4, 7),
+                    (3, 7),
+                    (1, 0),
+                    (1, 0),
+                ],
+            ));
+            frame.extend((frame.len()..LEN).map(|_| rng.u8(..)));
+            if crc {
+                let crc = crc16(&[&frame[2..4], &frame[6..15]]);
+                frame[4..6].copy_from_slice(&crc.to_be_bytes());
+            }
+            stream.extend(frame);
+        }
+        stream
+    }
+
+    fn decode(bytes: Vec<u8>) -> Vec<f32> {
+        audio_merger::decode(probe::open_bytes(bytes, "mp3").unwrap())
+            .unwrap()
+            .samples
+    }
+
+    #[test]
+    fn adjusted_frames_decode_at_the_new_level() {
+        for crc in [false, true] {
+            let stream = noise(crc, 20);
+            let mut quieter = stream.clone();
+            for frame in quieter.chunks_mut(192) {
+                adjust_gain(frame, -4);
+                if crc {
+                    let expected = crc16(&[&frame[2..4], &frame[6..15]]);
+                    assert_eq!(frame[4..6], expected.to_be_bytes());
+                }
+            }
+
+            let before = decode(stream);
+            let after = decode(quieter);
+            assert_eq!(before.len(), after.len());
+            let energy = |samples: &[f32]| samples.iter().map(|s| (*s as f64).powi(2)).sum::<f64>();
+            assert!(energy(&before) > 0.0);
+            // Four steps of 2^(1/4) halve the amplitude
+            let ratio = (energy(&after) / energy(&before)).sqrt();
+            assert!((ratio - 0.5).abs() < 0.005, "crc {}: ratio {}", crc, ratio);
+        }
+    }
+
+    #[test]
+    fn silent_frames_decode_to_silence() {
+        let stream = noise(false, 4);
+        let silent = silent_frame(&stream[..192]).repeat(4);
+        assert_eq!(samples_per_frame(&silent), 576);
+        assert!(decode(silent).iter().all(|sample| *sample == 0.0));
+    }
+}
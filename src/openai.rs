@@ -0,0 +1,61 @@
+use reqwest::{Client, RequestBuilder};
+use std::collections::HashMap;
+
+use crate::config::{CompatibleConfig, ModelConfig};
+
+const DEFAULT_OPENAI_BASE_URL: &str = "https://api.openai.com/v1";
+
+// Where OpenAI-style chat completion and speech requests are sent
+#[derive(Debug, Clone)]
+pub struct OpenAIEndpoint {
+    pub base_url: String,
+    pub api_key: Option<String>,
+    pub headers: HashMap<String, String>,
+}
+
+impl OpenAIEndpoint {
+    fn new(base_url: &str, api_key: Option<&String>, headers: &HashMap<String, String>) -> Self {
+        Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            api_key: api_key.filter(|key| !key.is_empty()).cloned(),
+            headers: headers.clone(),
+        }
+    }
+
+    pub fn from_config(config: &ModelConfig) -> Self {
+        Self::new(
+            config
+                .openai_base_url
+                .as_deref()
+                .unwrap_or(DEFAULT_OPENAI_BASE_URL),
+            config.openai_api_key.as_ref(),
+            &config.openai_headers,
+        )
+    }
+
+    // The compatible provider has no default server to fall back to
+    pub fn compatible(config: &CompatibleConfig) -> Option<Self> {
+        let base_url = config.base_url.as_deref()?;
+        Some(Self::new(
+            base_url,
+            config.api_key.as_ref(),
+            &config.headers,
+        ))
+    }
+
+    // Self-hosted compatible servers usually run without authentication
+    pub fn is_official(&self) -> bool {
+        self.base_url == DEFAULT_OPENAI_BASE_URL
+    }
+
+    pub fn post(&self, client: &Client, path: &str) -> RequestBuilder {
+        let mut request = client.post(format!("{}{}", self.base_url, path));
+        if let Some(api_key) = &self.api_key {
+            request = request.header("Authorization", format!("Bearer {}", api_key));
+        }
+        for (name, value) in &self.headers {
+            request = request.header(name, value);
+        }
+        request
+    }
+}
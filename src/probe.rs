@@ -0,0 +1,115 @@
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::Cursor;
+use std::path::Path;
+use std::time::Duration;
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::{FormatOptions, FormatReader};
+use symphonia::core::io::{MediaSource, MediaSourceStream};
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+use crate::audio_merger;
+
+// What a fully decoded clip or file turned out to be
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AudioInfo {
+    pub duration_secs: f64,
+    pub sample_rate: u32,
+    pub channels: usize,
+    // Average over the whole file, container overhead included
+    pub bitrate_kbps: u32,
+}
+
+pub fn open(path: &Path) -> Result<Box<dyn FormatReader>> {
+    let file = File::open(path).with_context(|| format!("Cannot open {}", path.display()))?;
+    let extension = path.extension().and_then(|e| e.to_str());
+    reader(Box::new(file), extension)
+        .with_context(|| format!("Unsupported audio file {}", path.display()))
+}
+
+// Audio still in memory, such as a clip straight from the TTS backend
+pub fn open_bytes(bytes: Vec<u8>, extension: &str) -> Result<Box<dyn FormatReader>> {
+    reader(Box::new(Cursor::new(bytes)), Some(extension))
+        .with_context(|| format!("Not a valid {} clip", extension))
+}
+
+fn reader(source: Box<dyn MediaSource>, extension: Option<&str>) -> Result<Box<dyn FormatReader>> {
+    let stream = MediaSourceStream::new(source, Default::default());
+
+    let mut hint = Hint::new();
+    if let Some(extension) = extension {
+        hint.with_extension(extension);
+    }
+
+    let probed = symphonia::default::get_probe().format(
+        &hint,
+        stream,
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    )?;
+    Ok(probed.format)
+}
+
+// Playing time of the default track. MP3s without a Xing/Info header don't state their
+// length, so their packets are walked and added up, which is cheap as nothing is decoded.
+pub fn duration(path: &Path) -> Result<Duration> {
+    length(open(path)?).with_context(|| format!("Cannot read {}", path.display()))
+}
+
+fn length(mut reader: Box<dyn FormatReader>) -> Result<Duration> {
+    let track = reader
+        .default_track()
+        .ok_or_else(|| anyhow!("No audio track"))?;
+    let track_id = track.id;
+    let params = track.codec_params.clone();
+
+    let frames = match params.n_frames {
+        Some(frames) => frames,
+        None => {
+            let mut frames = 0;
+            loop {
+                match reader.next_packet() {
+                    Ok(packet) if packet.track_id() == track_id => frames += packet.dur,
+                    Ok(_) => {}
+                    Err(SymphoniaError::IoError(e))
+                        if e.kind() == std::io::ErrorKind::UnexpectedEof =>
+                    {
+                        break
+                    }
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            frames
+        }
+    };
+
+    match (params.time_base, params.sample_rate) {
+        (Some(time_base), _) => {
+            let time = time_base.calc_time(frames);
+            Ok(Duration::from_secs(time.seconds) + Duration::from_secs_f64(time.frac))
+        }
+        (None, Some(rate)) => Ok(Duration::from_secs_f64(frames as f64 / rate as f64)),
+        (None, None) => Err(anyhow!("Unknown sample rate")),
+    }
+}
+
+// Decodes every packet, so a corrupt or truncated frame fails here rather than in a player
+pub fn inspect(bytes: Vec<u8>, extension: &str) -> Result<AudioInfo> {
+    let size = bytes.len();
+    let pcm = audio_merger::decode(open_bytes(bytes, extension)?)
+        .with_context(|| format!("Cannot decode {} audio", extension))?;
+    let frames = pcm.samples.len() / pcm.channels.max(1);
+    if frames == 0 || pcm.sample_rate == 0 {
+        return Err(anyhow!("{} audio decodes to no samples", extension));
+    }
+
+    let duration_secs = frames as f64 / pcm.sample_rate as f64;
+    Ok(AudioInfo {
+        duration_secs,
+        sample_rate: pcm.sample_rate,
+        channels: pcm.channels,
+        bitrate_kbps: (size as f64 * 8.0 / duration_secs / 1000.0).round() as u32,
+    })
+}
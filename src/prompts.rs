@@ -0,0 +1,88 @@
+use anyhow::{anyhow, Context, Result};
+use minijinja::{Environment, UndefinedBehavior, Value};
+use serde::Deserialize;
+use std::path::Path;
+
+use crate::config::PromptConfig;
+
+// Shipped with the binary, a file of the same name in PROMPT_DIR takes precedence
+const BUILTIN_PRESETS: &[(&str, &str)] = &[
+    ("conversation", include_str!("../prompts/conversation.toml")),
+    ("interview", include_str!("../prompts/interview.toml")),
+    ("debate", include_str!("../prompts/debate.toml")),
+    ("lecture", include_str!("../prompts/lecture.toml")),
+];
+
+// System and user message templates, rendered with title, content, speakers and target_length
+#[derive(Debug, Clone, Deserialize)]
+pub struct PromptPreset {
+    #[serde(skip)]
+    pub name: String,
+    pub system: String,
+    pub user: String,
+}
+
+impl PromptPreset {
+    pub fn load(config: &PromptConfig) -> Result<Self> {
+        let name = config.preset.as_str();
+        let custom = config
+            .dir
+            .as_deref()
+            .map(|dir| dir.join(format!("{}.toml", name)))
+            .filter(|path| path.exists());
+
+        let mut preset: Self = match custom {
+            Some(path) => parse(&std::fs::read_to_string(&path)?, &path)?,
+            None => {
+                let (_, source) = BUILTIN_PRESETS
+                    .iter()
+                    .find(|(builtin, _)| *builtin == name)
+                    .ok_or_else(|| {
+                        anyhow!(
+                            "Unknown prompt preset {}, built-in presets are {}",
+                            name,
+                            builtin_names()
+                        )
+                    })?;
+                parse(source, Path::new(name))?
+            }
+        };
+        preset.name = name.to_string();
+        Ok(preset)
+    }
+}
+
+fn parse(source: &str, path: &Path) -> Result<PromptPreset> {
+    let preset: PromptPreset = toml::from_str(source)
+        .with_context(|| format!("Invalid prompt preset {}", path.display()))?;
+    Ok(PromptPreset {
+        system: preset.system.trim().to_string(),
+        user: preset.user.trim().to_string(),
+        ..preset
+    })
+}
+
+fn builtin_names() -> String {
+    BUILTIN_PRESETS
+        .iter()
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+// Strict so that a typo in a variable name fails instead of rendering as nothing
+pub fn render(template: &str, context: Value) -> Result<String> {
+    let mut environment = Environment::new();
+    environment.set_undefined_behavior(UndefinedBehavior::Strict);
+    environment
+        .render_str(template, context)
+        .map_err(|e| anyhow!("Failed to render prompt template: {:#}", e))
+}
+
+pub fn uses_variable(template: &str, name: &str) -> Result<bool> {
+    let environment = Environment::new();
+    let template = environment
+        .template_from_str(template)
+        .map_err(|e| anyhow!("Invalid prompt template: {:#}", e))?;
+    Ok(template.undeclared_variables(false).contains(name))
+}
@@ -0,0 +1,123 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::manifest::Stage;
+
+const REPORT_FILE: &str = "failures.json";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Failure {
+    pub stage: Stage,
+    pub file: PathBuf,
+    // Outermost error first, followed by its causes
+    pub errors: Vec<String>,
+}
+
+// Collects per-file failures when the batch keeps going past errors
+pub struct FailureReport {
+    keep_going: bool,
+    failures: Mutex<Vec<Failure>>,
+}
+
+impl FailureReport {
+    pub fn new(keep_going: bool) -> Self {
+        Self {
+            keep_going,
+            failures: Mutex::new(Vec::new()),
+        }
+    }
+
+    // Records the error and swallows it in keep-going mode, otherwise hands it back
+    pub fn handle(&self, stage: Stage, file: &Path, result: Result<()>) -> Result<()> {
+        match result {
+            Err(e) if self.keep_going => {
+                println!("Stage {} failed for {}: {:#}", stage, file.display(), e);
+                self.lock().push(Failure {
+                    stage,
+                    file: file.to_path_buf(),
+                    errors: e.chain().map(|cause| cause.to_string()).collect(),
+                });
+                Ok(())
+            }
+            result => result,
+        }
+    }
+
+    // Files without a failure so far, later stages skip documents an earlier stage gave up on
+    pub fn remaining(&self, files: &[PathBuf]) -> Vec<PathBuf> {
+        let failures = self.lock();
+        files
+            .iter()
+            .filter(|file| !failures.iter().any(|failure| &failure.file == *file))
+            .cloned()
+            .collect()
+    }
+
+    pub fn failures(&self) -> Vec<Failure> {
+        self.lock().clone()
+    }
+
+    // Prints the summary table and writes the JSON report, a clean run removes a stale report
+    pub fn finish(&self, output_dir: &Path) -> Result<()> {
+        let report_path = output_dir.join(REPORT_FILE);
+        let failures = self.failures();
+
+        if failures.is_empty() {
+            if report_path.exists() {
+                std::fs::remove_file(&report_path)?;
+            }
+            return Ok(());
+        }
+
+        print_table(&failures);
+        std::fs::write(&report_path, serde_json::to_string_pretty(&failures)?)?;
+        println!("Failure report written to {}", report_path.display());
+        Ok(())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<Failure>> {
+        self.failures
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+fn print_table(failures: &[Failure]) {
+    let rows: Vec<[String; 3]> = failures
+        .iter()
+        .map(|failure| {
+            [
+                failure.stage.to_string(),
+                failure.file.display().to_string(),
+                failure.errors.join(": "),
+            ]
+        })
+        .collect();
+
+    let headers = ["Stage", "File", "Error"];
+    let width = |column: usize| {
+        rows.iter()
+            .map(|row| row[column].chars().count())
+            .chain([headers[column].len()])
+            .max()
+            .unwrap_or_default()
+    };
+    let (stage_width, file_width) = (width(0), width(1));
+
+    println!("\n{} file(s) failed:", failures.len());
+    println!(
+        "{:<stage_width$}  {:<file_width$}  {}",
+        headers[0], headers[1], headers[2]
+    );
+    println!(
+        "{}  {}  {}",
+        "-".repeat(stage_width),
+        "-".repeat(file_width),
+        "-".repeat(5)
+    );
+    for [stage, file, error] in rows {
+        println!("{:<stage_width$}  {:<file_width$}  {}", stage, file, error);
+    }
+}
@@ -0,0 +1,272 @@
+use anyhow::{anyhow, Result};
+use reqwest::{header::HeaderMap, RequestBuilder, Response, StatusCode};
+use std::future::Future;
+use std::time::{Duration, SystemTime};
+
+use crate::config::RetryConfig;
+
+// Why an attempt failed and whether trying again can help
+pub enum Failure {
+    Retryable {
+        error: anyhow::Error,
+        // Server-provided wait from Retry-After, overrides the computed backoff
+        retry_after: Option<Duration>,
+    },
+    Fatal(anyhow::Error),
+}
+
+impl Failure {
+    pub fn retryable(error: anyhow::Error) -> Self {
+        Failure::Retryable {
+            error,
+            retry_after: None,
+        }
+    }
+}
+
+impl RetryConfig {
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout_secs)
+    }
+
+    // Exponential backoff with jitter so that parallel workers don't retry in lockstep
+    fn backoff(&self, retry: u32) -> Duration {
+        let exponential = self
+            .initial_backoff_ms
+            .saturating_mul(2u64.saturating_pow(retry))
+            .min(self.max_backoff_ms);
+        let jittered = exponential / 2 + fastrand::u64(0..=exponential / 2);
+        Duration::from_millis(jittered)
+    }
+}
+
+pub async fn retry<T, F, Fut>(config: &RetryConfig, label: &str, mut attempt: F) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = std::result::Result<T, Failure>>,
+{
+    let mut retries = 0;
+    loop {
+        match attempt().await {
+            Ok(value) => return Ok(value),
+            Err(Failure::Fatal(error)) => return Err(error.context(format!("{} failed", label))),
+            Err(Failure::Retryable { error, retry_after }) => {
+                if retries >= config.max_retries {
+                    return Err(error.context(format!(
+                        "{} failed after {} attempts",
+                        label,
+                        retries + 1
+                    )));
+                }
+
+                let delay = retry_after.unwrap_or_else(|| config.backoff(retries));
+                retries += 1;
+                println!(
+                    "{} failed: {:#}, retrying in {:.1}s ({}/{})",
+                    label,
+                    error,
+                    delay.as_secs_f32(),
+                    retries,
+                    config.max_retries
+                );
+                tokio::time::sleep(delay).await;
+            }
+        }
+    }
+}
+
+// Send a request built fresh for every attempt and return the body of the first success
+pub async fn send(
+    config: &RetryConfig,
+    label: &str,
+    request: impl Fn() -> RequestBuilder,
+) -> Result<Vec<u8>> {
+    retry(config, label, || async {
+        let response = request()
+            .timeout(config.timeout())
+            .send()
+            .await
+            .map_err(transport_failure)?;
+        let response = check_status(response).await?;
+        let body = response.bytes().await.map_err(transport_failure)?;
+        Ok(body.to_vec())
+    })
+    .await
+}
+
+// Like send, but hands back the first successful response for the caller to stream.
+// The timeout only covers waiting for the headers, not reading the body.
+pub async fn open(
+    config: &RetryConfig,
+    label: &str,
+    request: impl Fn() -> RequestBuilder,
+) -> Result<Response> {
+    retry(config, label, || async {
+        let response = tokio::time::timeout(config.timeout(), request().send())
+            .await
+            .map_err(|_| Failure::retryable(anyhow!("no response after {}s", config.timeout_secs)))?
+            .map_err(transport_failure)?;
+        check_status(response).await
+    })
+    .await
+}
+
+async fn check_status(response: Response) -> std::result::Result<Response, Failure> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    let retry_after = retry_after(response.headers());
+    let body = response.text().await.unwrap_or_default();
+    let error = anyhow!("{}: {}", status, body.trim());
+    if is_retryable(status, &body) {
+        Err(Failure::Retryable { error, retry_after })
+    } else {
+        Err(Failure::Fatal(error))
+    }
+}
+
+fn is_retryable(status: StatusCode, body: &str) -> bool {
+    match status {
+        // An exhausted quota also comes back as 429 but waiting won't fix it
+        StatusCode::TOO_MANY_REQUESTS => !body.contains("insufficient_quota"),
+        StatusCode::REQUEST_TIMEOUT => true,
+        status => status.is_server_error(),
+    }
+}
+
+fn transport_failure(error: reqwest::Error) -> Failure {
+    if error.is_timeout() || error.is_connect() || error.is_request() || error.is_body() {
+        Failure::retryable(error.into())
+    } else {
+        Failure::Fatal(error.into())
+    }
+}
+
+// Longest server-requested wait that is honored, anything beyond is cut down to this
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(600);
+
+// Retry-After in seconds or as an HTTP date, or the millisecond variant some
+// OpenAI-compatible servers send
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::trim)
+    };
+    // Too large to represent is as good as the cap
+    let number = |value: &str, unit_secs: f64| {
+        value
+            .parse::<f64>()
+            .ok()
+            .filter(|value| *value >= 0.0)
+            .map(|value| Duration::try_from_secs_f64(value * unit_secs).unwrap_or(MAX_RETRY_AFTER))
+    };
+    let date = |value: &str| {
+        httpdate::parse_http_date(value)
+            .ok()
+            .map(|date| date.duration_since(SystemTime::now()).unwrap_or_default())
+    };
+
+    header("retry-after-ms")
+        .and_then(|value| number(value, 0.001))
+        .or_else(|| {
+            header("retry-after").and_then(|value| number(value, 1.0).or_else(|| date(value)))
+        })
+        .map(|delay| delay.min(MAX_RETRY_AFTER))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn retryable_statuses() {
+        assert!(is_retryable(StatusCode::TOO_MANY_REQUESTS, "slow down"));
+        assert!(!is_retryable(
+            StatusCode::TOO_MANY_REQUESTS,
+            r#"{"error":{"code":"insufficient_quota"}}"#
+        ));
+        assert!(is_retryable(StatusCode::REQUEST_TIMEOUT, ""));
+        assert!(is_retryable(StatusCode::INTERNAL_SERVER_ERROR, ""));
+        assert!(is_retryable(StatusCode::SERVICE_UNAVAILABLE, ""));
+        assert!(!is_retryable(StatusCode::BAD_REQUEST, ""));
+        assert!(!is_retryable(StatusCode::UNAUTHORIZED, ""));
+        assert!(!is_retryable(StatusCode::NOT_FOUND, ""));
+    }
+
+    #[test]
+    fn retry_after_seconds_and_milliseconds() {
+        let delay = |pairs| retry_after(&headers(pairs));
+        assert_eq!(delay(&[("retry-after", "3")]), Some(Duration::from_secs(3)));
+        assert_eq!(
+            delay(&[("retry-after", "1.5")]),
+            Some(Duration::from_millis(1500))
+        );
+        assert_eq!(
+            delay(&[("retry-after-ms", "250"), ("retry-after", "3")]),
+            Some(Duration::from_millis(250))
+        );
+        assert_eq!(delay(&[("retry-after", "-1")]), None);
+        assert_eq!(delay(&[("retry-after", "soon")]), None);
+        assert_eq!(delay(&[]), None);
+    }
+
+    #[test]
+    fn retry_after_is_capped() {
+        let delay = |pairs| retry_after(&headers(pairs));
+        assert_eq!(
+            delay(&[("retry-after", "99999999999999999999")]),
+            Some(MAX_RETRY_AFTER)
+        );
+        assert_eq!(delay(&[("retry-after", "inf")]), Some(MAX_RETRY_AFTER));
+        assert_eq!(delay(&[("retry-after-ms", "1e30")]), Some(MAX_RETRY_AFTER));
+        assert_eq!(delay(&[("retry-after", "NaN")]), None);
+    }
+
+    #[test]
+    fn retry_after_http_date() {
+        let date = |time: SystemTime| {
+            let value = httpdate::fmt_http_date(time);
+            retry_after(&headers(&[("retry-after", &value)])).unwrap()
+        };
+
+        let soon = date(SystemTime::now() + Duration::from_secs(30));
+        assert!(soon > Duration::from_secs(28) && soon <= Duration::from_secs(30));
+        assert_eq!(
+            date(SystemTime::now() - Duration::from_secs(30)),
+            Duration::ZERO
+        );
+        assert_eq!(
+            date(SystemTime::now() + Duration::from_secs(86_400)),
+            MAX_RETRY_AFTER
+        );
+    }
+
+    #[test]
+    fn backoff_doubles_with_jitter_up_to_the_maximum() {
+        let config = RetryConfig {
+            initial_backoff_ms: 1000,
+            max_backoff_ms: 5000,
+            ..RetryConfig::default()
+        };
+        for _ in 0..100 {
+            for (retry, full_ms) in [(0, 1000), (1, 2000), (2, 4000), (3, 5000), (40, 5000)] {
+                let delay = config.backoff(retry);
+                assert!(delay >= Duration::from_millis(full_ms / 2), "{:?}", delay);
+                assert!(delay <= Duration::from_millis(full_ms), "{:?}", delay);
+            }
+        }
+    }
+}
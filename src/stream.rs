@@ -0,0 +1,121 @@
+use anyhow::{anyhow, Result};
+use reqwest::Response;
+use std::fs::File;
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::format_elapsed;
+
+// How often the live progress line is redrawn
+const REDRAW_INTERVAL: Duration = Duration::from_millis(250);
+
+// Feeds each line of a streamed body to on_line until it returns true or the body ends.
+// Only a stall longer than idle_timeout fails, however long the whole response takes.
+pub async fn for_each_line(
+    mut response: Response,
+    idle_timeout: Duration,
+    mut on_line: impl FnMut(&str) -> Result<bool>,
+) -> Result<()> {
+    let mut buffer = Vec::new();
+    loop {
+        let chunk = tokio::time::timeout(idle_timeout, response.chunk())
+            .await
+            .map_err(|_| anyhow!("stream stalled for {}s", idle_timeout.as_secs()))??;
+        let Some(chunk) = chunk else {
+            break;
+        };
+
+        buffer.extend_from_slice(&chunk);
+        while let Some(end) = buffer.iter().position(|&byte| byte == b'\n') {
+            let line: Vec<u8> = buffer.drain(..=end).collect();
+            if on_line(String::from_utf8_lossy(&line).trim())? {
+                return Ok(());
+            }
+        }
+    }
+
+    let rest = String::from_utf8_lossy(&buffer);
+    if !rest.trim().is_empty() {
+        on_line(rest.trim())?;
+    }
+    Ok(())
+}
+
+// Live tokens/sec line on a terminal, a single summary line otherwise. Streams running side by
+// side would all redraw the same line, so they only print the summary.
+pub struct Progress {
+    label: String,
+    started: Instant,
+    tokens: usize,
+    last_draw: Instant,
+    live: bool,
+}
+
+impl Progress {
+    pub fn new(label: &str, live: bool) -> Self {
+        let now = Instant::now();
+        Self {
+            label: label.to_string(),
+            started: now,
+            tokens: 0,
+            last_draw: now,
+            live: live && std::io::stderr().is_terminal(),
+        }
+    }
+
+    // Streamed chunks are counted as tokens, servers send roughly one per chunk
+    pub fn token(&mut self) {
+        self.tokens += 1;
+        if self.live && self.last_draw.elapsed() >= REDRAW_INTERVAL {
+            self.last_draw = Instant::now();
+            eprint!("\r\x1b[2K{}", self.status());
+        }
+    }
+
+    pub fn finish(&self) {
+        if self.live {
+            eprint!("\r\x1b[2K");
+        }
+        println!("{}", self.status());
+    }
+
+    fn status(&self) -> String {
+        let elapsed = self.started.elapsed();
+        format!(
+            "{}: {} tokens, {:.1} tokens/s, {}",
+            self.label,
+            self.tokens,
+            self.tokens as f64 / elapsed.as_secs_f64().max(0.001),
+            format_elapsed(elapsed)
+        )
+    }
+}
+
+// Text generated so far for one document, on disk so that an interrupted run keeps it
+pub struct PartialOutput {
+    path: PathBuf,
+    file: File,
+}
+
+impl PartialOutput {
+    pub fn create(path: &Path) -> Result<Self> {
+        Ok(Self {
+            path: path.to_path_buf(),
+            file: File::create(path)?,
+        })
+    }
+
+    pub fn push(&mut self, text: &str) -> Result<()> {
+        self.file.write_all(text.as_bytes())?;
+        self.file.flush()?;
+        Ok(())
+    }
+
+    // The complete conversation has been saved, the partial copy is no longer needed
+    pub fn finish(self) -> Result<()> {
+        drop(self.file);
+        std::fs::remove_file(&self.path)?;
+        Ok(())
+    }
+}